@@ -1,9 +1,31 @@
 //! Thread-safe reference-counting pointers. ‘Arc’ stands for ‘Atomically Reference Counted’.
 //!
 //! This module is only included when the "arc" feature is enabled, but that feature is enabled by default.
+//!
+//! Because an [`Arc`] can be sent to another thread based only on its target type, regardless of
+//! what else the allocation it refers to contains, the entire value of every allocation must be
+//! both [`Send`] and [`Sync`]. Allocations that contain anything that isn't thread-safe are
+//! rejected at compile time:
+//!
+//! ```compile_fail,E0277
+//! let p = maprc::arc::Arc::new((1_u64, std::rc::Rc::new(2_u64)));
+//! let first = maprc::arc::Arc::map(p, |v| &v.0);
+//! ```
+//!
+//! ```compile_fail,E0277
+//! let alloc = std::sync::Arc::new((1_u64, std::cell::Cell::new(2_u64)));
+//! let p = maprc::arc::Arc::from_alloc(alloc);
+//! ```
+//!
+//! ```compile_fail,E0277
+//! let alloc = std::sync::Arc::new((1_u64, std::cell::Cell::new(2_u64)));
+//! let first = unsafe { maprc::arc::Arc::from_raw_parts(&alloc.0 as *const u64, alloc.clone()) };
+//! ```
 
 pub use crate::macroed::arc::*;
 
+// SAFETY: The allocation that owns the target is always `Send + Sync` itself,
+// so only the target type needs to be considered.
 unsafe impl<T: ?Sized + Sync + Send> Sync for Arc<T> {}
 unsafe impl<T: ?Sized + Sync + Send> Send for Arc<T> {}
 impl<T: ?Sized + core::panic::RefUnwindSafe> core::panic::UnwindSafe for Arc<T> {}
@@ -12,6 +34,23 @@ unsafe impl<T: ?Sized + Sync + Send> Sync for Weak<T> {}
 unsafe impl<T: ?Sized + Sync + Send> Send for Weak<T> {}
 impl<T: ?Sized> core::panic::UnwindSafe for Weak<T> {}
 impl<T: ?Sized> core::panic::RefUnwindSafe for Weak<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Sync for UniqueArc<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Send for UniqueArc<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Sync for ScopedArc<'_, T> {}
+unsafe impl<T: ?Sized + Sync + Send> Send for ScopedArc<'_, T> {}
+unsafe impl<T: ?Sized + Sync + Send> Sync for ScopedWeak<'_, T> {}
+unsafe impl<T: ?Sized + Sync + Send> Send for ScopedWeak<'_, T> {}
+unsafe impl<A: Sync + Send, T: ?Sized + Sync + Send> Sync for TypedArc<A, T> {}
+unsafe impl<A: Sync + Send, T: ?Sized + Sync + Send> Send for TypedArc<A, T> {}
+
+impl Arc<dyn core::any::Any + Send + Sync> {
+    /// Attempts to downcast the target to a concrete type, within the same allocation.
+    ///
+    /// Returns the original pointer if the target is not of type `T`.
+    pub fn downcast<T: core::any::Any>(this: Self) -> Result<Arc<T>, Self> {
+        Self::filter_map(this, |v| v.downcast_ref::<T>())
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -26,6 +65,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     pub fn from_static_ref() {
         static V: u64 = 39;
         let p = Arc::from_static_ref(&V);
@@ -128,4 +168,882 @@ mod tests {
         assert_eq!(foo_a.map(|r| *r), Some(3));
         assert_eq!(foo_b.map(|r| *r), None);
     }
+
+    #[test]
+    pub fn map() {
+        struct Foo {
+            a: u64,
+            b: u64,
+        }
+        let foo = Arc::new(Foo { a: 3, b: 4 });
+        let foo_b: Arc<u64> = Arc::clone_map(&foo, |foo| &foo.b);
+        let foo_a: Arc<u64> = Arc::map(foo, |foo| &foo.a);
+        assert_eq!(
+            Arc::strong_count(&foo_a),
+            2,
+            "pointer to a has wrong strong_count",
+        );
+        assert_eq!(*foo_a, 3);
+        assert_eq!(*foo_b, 4);
+    }
+
+    #[test]
+    pub fn filter_map() {
+        let p = Arc::new(Some(3_u64));
+        let p: Arc<u64> = Arc::filter_map(p, |v| v.as_ref()).ok().unwrap();
+        assert_eq!(*p, 3);
+
+        let p = Arc::new(None::<u64>);
+        let p: Arc<Option<u64>> = Arc::filter_map(p, |v| v.as_ref()).unwrap_err();
+        assert_eq!(*p, None);
+    }
+
+    #[test]
+    pub fn try_map() {
+        let p = Arc::new([1_u64, 2, 3]);
+        let elem: Arc<u64> = Arc::try_map(p, |v| v.get(1).ok_or("out of range"))
+            .ok()
+            .unwrap();
+        assert_eq!(*elem, 2);
+        assert_eq!(Arc::strong_count(&elem), 1);
+
+        let p = Arc::new([1_u64, 2, 3]);
+        let (p, err) = Arc::try_map(p, |v| v.get(5).ok_or("out of range"))
+            .err()
+            .unwrap();
+        assert_eq!(*p, [1, 2, 3]);
+        assert_eq!(err, "out of range");
+    }
+
+    #[test]
+    pub fn map_split() {
+        struct Foo {
+            a: u64,
+            b: u64,
+            c: [u8; 2],
+        }
+        let foo = Arc::new(Foo {
+            a: 3,
+            b: 4,
+            c: *b"hi",
+        });
+        let (foo_a, foo_b) = Arc::map_split(Arc::clone(&foo), |foo| (&foo.a, &foo.b));
+        assert_eq!(*foo_a, 3);
+        assert_eq!(*foo_b, 4);
+        assert_eq!(
+            Arc::strong_count(&foo),
+            3,
+            "pointer to foo has wrong strong_count",
+        );
+        drop((foo_a, foo_b));
+
+        let (foo_a, foo_b, foo_c) = Arc::map_split3(foo, |foo| (&foo.a, &foo.b, &foo.c[..]));
+        assert_eq!(*foo_a, 3);
+        assert_eq!(*foo_b, 4);
+        assert_eq!(&*foo_c, b"hi");
+        assert_eq!(
+            Arc::strong_count(&foo_c),
+            3,
+            "pointer to c has wrong strong_count",
+        );
+    }
+
+    #[test]
+    pub fn iter_map() {
+        let v = Arc::new(alloc::vec![1_u64, 2, 3]);
+        let iter = Arc::iter_map(&v, |v| v);
+        assert_eq!(iter.len(), 3);
+        let elems: alloc::vec::Vec<Arc<u64>> = iter.rev().collect();
+        drop(v); // The elements can safely outlive the original
+        assert_eq!(
+            Arc::strong_count(&elems[0]),
+            3,
+            "pointer to element has wrong strong_count",
+        );
+        assert_eq!(*elems[0], 3);
+        assert_eq!(*elems[1], 2);
+        assert_eq!(*elems[2], 1);
+    }
+
+    #[test]
+    pub fn slice() {
+        let buf = Arc::new([0_u8, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let all: Arc<[u8]> = Arc::clone_map(&buf, |b| &b[..]);
+        let part = Arc::slice(&all, 3..7);
+        assert_eq!(&*part, &[3, 4, 5, 6]);
+
+        let (l, r) = Arc::split_at(&part, 1);
+        assert_eq!(&*l, &[3]);
+        assert_eq!(&*r, &[4, 5, 6]);
+
+        let (first, rest) = Arc::split_first(&r).unwrap();
+        assert_eq!(*first, 4);
+        assert_eq!(&*rest, &[5, 6]);
+        let (last, rest) = Arc::split_last(&r).unwrap();
+        assert_eq!(*last, 6);
+        assert_eq!(&*rest, &[4, 5]);
+        assert!(Arc::split_first(&Arc::slice(&all, 0..0)).is_none());
+
+        let chunks: alloc::vec::Vec<Arc<[u8]>> = Arc::chunks(&all, 4).collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(&*chunks[2], &[8, 9]);
+
+        let arr: Arc<[u8; 4]> = Arc::slice(&all, 4..8).try_into().unwrap();
+        assert_eq!(*arr, [4, 5, 6, 7]);
+        let not_arr: Result<Arc<[u8; 4]>, _> = Arc::slice(&all, 4..9).try_into();
+        assert_eq!(&*not_arr.unwrap_err(), &[4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    pub fn slice_str() {
+        let buf = Arc::new(*b"hello world\nsecond line");
+        let bytes: Arc<[u8]> = Arc::clone_map(&buf, |b| &b[..]);
+        let s = Arc::<str>::from_utf8(bytes).unwrap();
+        assert_eq!(&*Arc::slice(&s, 6..11), "world");
+
+        let (l, r) = Arc::split_at(&s, 5);
+        assert_eq!(&*l, "hello");
+        assert_eq!(&*r, " world\nsecond line");
+
+        let lines: alloc::vec::Vec<Arc<str>> = Arc::lines(&s).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(&*lines[1], "second line");
+
+        let words: alloc::vec::Vec<Arc<str>> = Arc::split(&lines[1], " ").collect();
+        assert_eq!(words.len(), 2);
+        assert_eq!(&*words[0], "second");
+
+        let bad: Arc<[u8]> = Arc::map(Arc::new([0xff_u8, 0xfe]), |b| &b[..]);
+        let (bad, _) = Arc::<str>::from_utf8(bad).unwrap_err();
+        assert_eq!(&*bad, &[0xff, 0xfe]);
+    }
+
+    #[test]
+    pub fn identity() {
+        struct Foo {
+            a: u64,
+            b: u64,
+        }
+        let foo = Arc::new(Foo { a: 3, b: 4 });
+        let foo_a: Arc<u64> = Arc::clone_map(&foo, |foo| &foo.a);
+        let foo_b: Arc<u64> = Arc::clone_map(&foo, |foo| &foo.b);
+        let other = Arc::new(3_u64);
+        assert!(Arc::ptr_eq(&foo_a, &Arc::clone(&foo_a)));
+        assert!(!Arc::ptr_eq(&foo_a, &foo_b));
+        assert!(!Arc::ptr_eq(&foo_a, &other));
+        assert!(Arc::same_allocation(&foo_a, &foo_b));
+        assert!(Arc::same_allocation(&foo_a, &foo));
+        assert!(!Arc::same_allocation(&foo_a, &other));
+
+        let offset_b = core::mem::offset_of!(Foo, b);
+        assert_eq!(Arc::offset_in_allocation(&foo_b), Some(offset_b));
+        assert_eq!(
+            Arc::byte_range_within(&foo_b, &foo),
+            Some(offset_b..offset_b + 8)
+        );
+        assert_eq!(Arc::byte_range_within(&foo_a, &foo_b), None);
+
+        static S: &str = "hello world";
+        let s = Arc::from_static_ref(S);
+        let world = Arc::slice(&s, 6..);
+        assert_eq!(Arc::byte_range_within(&world, &s), Some(6..11));
+        assert_eq!(Arc::offset_in_allocation(&world), None);
+        assert!(!Arc::same_allocation(&world, &s));
+        assert!(Arc::ptr_eq(&world, &Arc::from_static_ref(&S[6..])));
+    }
+
+    #[test]
+    pub fn by_address() {
+        use alloc::collections::BTreeSet;
+
+        let v = Arc::new([1_u64, 1]);
+        let a: Arc<u64> = Arc::clone_map(&v, |v| &v[0]);
+        let b: Arc<u64> = Arc::clone_map(&v, |v| &v[1]);
+        assert_eq!(a, b, "values should be equal");
+
+        let mut set = BTreeSet::new();
+        assert!(set.insert(ByAddress(a.clone())));
+        assert!(set.insert(ByAddress(b)));
+        assert!(!set.insert(ByAddress(a)));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    pub fn get_mut() {
+        struct Foo {
+            a: u64,
+            b: [u8; 4],
+        }
+        let foo = Arc::new(Foo { a: 3, b: *b"abcd" });
+        assert_eq!(foo.a, 3);
+        let mut foo_b: Arc<[u8]> = Arc::map(foo, |foo| &foo.b[..]);
+        Arc::get_mut(&mut foo_b).unwrap()[1] = b'x';
+        assert_eq!(&*foo_b, b"axcd");
+
+        let other = Arc::clone(&foo_b);
+        assert!(
+            Arc::get_mut(&mut foo_b).is_none(),
+            "shared target is mutable"
+        );
+        drop(other);
+        let weak = Arc::downgrade(&foo_b);
+        assert!(
+            Arc::get_mut(&mut foo_b).is_none(),
+            "shared target is mutable"
+        );
+        drop(weak);
+        assert!(Arc::get_mut(&mut foo_b).is_some());
+
+        static V: u64 = 3;
+        let mut s = Arc::from_static_ref(&V);
+        assert!(Arc::get_mut(&mut s).is_none(), "static target is mutable");
+
+        let mut v = Arc::new(alloc::vec![1_u64, 2, 3]);
+        Arc::get_mut(&mut v).unwrap().push(4);
+        let mut elem: Arc<u64> = Arc::map(v, |v| &v[3]);
+        assert_eq!(*elem, 4);
+        assert!(
+            Arc::get_mut(&mut elem).is_none(),
+            "target outside of allocation is mutable"
+        );
+    }
+
+    #[test]
+    pub fn make_mut() {
+        #[derive(Clone)]
+        struct Foo {
+            a: u64,
+            b: u64,
+        }
+        // SAFETY: The derived Clone implementation preserves the layout.
+        let foo = unsafe { Arc::new_cloneable(Foo { a: 3, b: 4 }) };
+        let mut foo_b: Arc<u64> = Arc::clone_map(&foo, |foo| &foo.b);
+        *Arc::make_mut(&mut foo_b).unwrap() = 5;
+        assert_eq!(*foo_b, 5);
+        assert_eq!(foo.b, 4, "original allocation was modified");
+        assert!(!Arc::same_allocation(&foo, &foo_b));
+        assert_eq!(Arc::strong_count(&foo), 1);
+        assert_eq!(Arc::strong_count(&foo_b), 1);
+
+        // Now that foo_b is unique, it should be modified in place.
+        let before = Arc::as_ptr(&foo_b);
+        *Arc::make_mut(&mut foo_b).unwrap() = 6;
+        assert_eq!(*foo_b, 6);
+        assert_eq!(Arc::as_ptr(&foo_b), before);
+        assert_eq!(foo.a, 3);
+
+        let mut not_cloneable = Arc::new(1_u64);
+        let _other = Arc::clone(&not_cloneable);
+        assert!(Arc::make_mut(&mut not_cloneable).is_none());
+    }
+
+    #[test]
+    pub fn try_unwrap() {
+        let p = Arc::new(alloc::string::String::from("hello"));
+        let other = Arc::clone(&p);
+        let p = Arc::try_unwrap(p).unwrap_err();
+        drop(other);
+        assert_eq!(Arc::try_unwrap(p).unwrap(), "hello");
+
+        let p = Arc::new((1_u64, 2_u64));
+        let first: Arc<u64> = Arc::map(p, |p| &p.0);
+        let first = Arc::try_unwrap(first).unwrap_err();
+        assert_eq!(Arc::unwrap_or_clone(first), 1);
+
+        let p = unsafe { Arc::new_cloneable(3_u64) };
+        let other = Arc::clone(&p);
+        let mut p = Arc::try_unwrap(p).unwrap_err();
+        *Arc::make_mut(&mut p).unwrap() = 4;
+        assert_eq!(Arc::into_inner(other), Some(3));
+        assert_eq!(Arc::into_inner(p), Some(4));
+
+        static V: u64 = 5;
+        let s = Arc::from_static_ref(&V);
+        assert!(Arc::try_unwrap(s.clone()).is_err());
+        assert_eq!(Arc::unwrap_or_clone(s), 5);
+    }
+
+    #[test]
+    pub fn try_into_alloc() {
+        let normal = alloc::sync::Arc::new(24_u64);
+        let p = Arc::from_alloc(alloc::sync::Arc::clone(&normal));
+        let back = Arc::try_into_alloc(p).unwrap();
+        assert!(alloc::sync::Arc::ptr_eq(&normal, &back));
+
+        let p = Arc::new((1_u64, 2_u64));
+        let second: Arc<u64> = Arc::map(p, |p| &p.1);
+        assert!(Arc::try_into_alloc(second).is_err());
+    }
+
+    #[test]
+    pub fn leak() {
+        let p = Arc::new(24_u64);
+        let weak = Arc::downgrade(&p);
+        let r: &'static u64 = Arc::leak(p);
+        assert_eq!(*r, 24);
+        assert!(weak.upgrade().is_some(), "leaked allocation was freed");
+
+        static V: u64 = 5;
+        assert!(core::ptr::eq(Arc::leak(Arc::from_static_ref(&V)), &V));
+    }
+
+    #[test]
+    pub fn unique() {
+        struct Node {
+            name: alloc::string::String,
+            parent: Weak<Node>,
+            children: alloc::vec::Vec<Arc<Node>>,
+        }
+        let mut root = UniqueArc::new(Node {
+            name: "root".into(),
+            parent: Weak::new(),
+            children: alloc::vec::Vec::new(),
+        });
+        let weak_root = UniqueArc::downgrade(&root);
+        assert!(
+            weak_root.upgrade().is_none(),
+            "weak upgraded before freezing"
+        );
+        for i in 0..2 {
+            let child = Arc::new(Node {
+                name: alloc::format!("child{i}"),
+                parent: UniqueArc::downgrade(&root),
+                children: alloc::vec::Vec::new(),
+            });
+            root.children.push(child);
+        }
+        let mut name = UniqueArc::map_mut(root, |n| &mut n.name);
+        name.push('!');
+        let name: Arc<alloc::string::String> = UniqueArc::into_arc(name);
+        assert_eq!(*name, "root!");
+
+        let root = weak_root
+            .upgrade()
+            .expect("weak not upgradeable after freezing");
+        assert_eq!(root.children.len(), 2);
+        let child_parent = root.children[1].parent.upgrade().unwrap();
+        assert_eq!(child_parent.name, "root!");
+        assert_eq!(Arc::strong_count(&root), 3);
+    }
+
+    #[test]
+    pub fn unique_dropped() {
+        let p = UniqueArc::new(5_u64);
+        let weak = UniqueArc::downgrade(&p);
+        drop(p);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(), 0);
+    }
+
+    #[test]
+    pub fn weak() {
+        let empty: Weak<str> = Weak::new();
+        assert!(empty.upgrade().is_none());
+        assert_eq!(empty.strong_count(), 0);
+        assert!(!empty.target_is_static());
+        assert!(empty.ptr_eq(&Weak::default()));
+
+        let p = Arc::new((1_u64, 2_u64));
+        let first = Arc::downgrade(&Arc::clone_map(&p, |p| &p.0));
+        let second = Arc::downgrade(&Arc::clone_map(&p, |p| &p.1));
+        let first_again = first.clone();
+        assert_eq!(first.weak_count(), 3);
+        assert!(first.ptr_eq(&first_again));
+        assert!(!first.ptr_eq(&second));
+        assert!(!first.ptr_eq(&Weak::new()));
+        assert!(first.same_allocation(&second));
+        assert!(!first.same_allocation(&Arc::downgrade(&Arc::new(1_u64))));
+        assert_eq!(alloc::format!("{:?}", first), "(Weak)");
+        assert_eq!(first_again.upgrade().map(|p| *p), Some(1));
+    }
+
+    #[test]
+    pub fn try_new_cyclic() {
+        struct Node {
+            me: Weak<Node>,
+            name: alloc::string::String,
+        }
+        let node = Arc::try_new_cyclic(|me| {
+            Ok::<_, ()>(Node {
+                me: me.clone(),
+                name: "a".into(),
+            })
+        })
+        .unwrap();
+        assert_eq!(node.me.upgrade().unwrap().name, "a");
+
+        let mut kept = None;
+        let err = Arc::try_new_cyclic(|me: &Weak<Node>| {
+            kept = Some(me.clone());
+            Err("invalid")
+        });
+        assert_eq!(err.err(), Some("invalid"));
+        let kept = kept.unwrap();
+        assert!(kept.upgrade().is_none());
+        assert_eq!(kept.strong_count(), 0);
+    }
+
+    #[test]
+    pub fn weak_clone_map() {
+        let p = Arc::new((1_u64, Some(2_u64)));
+        let weak = Arc::downgrade(&p);
+        let first: Weak<u64> = weak.clone_map(|p| &p.0).unwrap();
+        let second: Weak<u64> = weak.clone_filter_map(|p| p.1.as_ref()).unwrap();
+        assert!(weak.clone_filter_map(|_| None::<&u64>).is_none());
+        assert_eq!(Arc::strong_count(&p), 1);
+        assert_eq!(Arc::weak_count(&p), 3);
+        assert!(first.same_allocation(&weak));
+        assert_eq!(first.upgrade().map(|p| *p), Some(1));
+        assert_eq!(second.upgrade().map(|p| *p), Some(2));
+
+        drop(p);
+        assert!(weak.clone_map(|p| &p.0).is_none());
+        assert!(first.upgrade().is_none());
+
+        static V: (u64, u64) = (3, 4);
+        let weak = Weak::from_static_ref(&V);
+        let second = weak.clone_map(|v| &v.1).unwrap();
+        assert!(second.target_is_static());
+        assert_eq!(second.upgrade().map(|p| *p), Some(4));
+    }
+
+    #[test]
+    pub fn downcast() {
+        let p = Arc::new((1_u64, alloc::string::String::from("hi")));
+        let second: Arc<dyn core::any::Any + Send + Sync> =
+            Arc::clone_map(&p, |p| &p.1 as &(dyn core::any::Any + Send + Sync));
+        let second = Arc::downcast::<u64>(second).unwrap_err();
+        let second = Arc::downcast::<alloc::string::String>(second).unwrap();
+        assert_eq!(*second, "hi");
+        assert!(Arc::same_allocation(&second, &p));
+    }
+
+    #[test]
+    pub fn owner_downcast() {
+        struct Document {
+            title: alloc::string::String,
+            body: alloc::string::String,
+        }
+        let doc = Arc::new(Document {
+            title: "Title".into(),
+            body: "Body".into(),
+        });
+        let title: Arc<str> = Arc::map(doc, |d| d.title.as_str());
+        assert!(Arc::owner_downcast::<alloc::string::String>(&title).is_none());
+        let doc = Arc::owner_downcast::<Document>(&title).unwrap();
+        assert_eq!(doc.body, "Body");
+        assert_eq!(Arc::strong_count(&doc), 2);
+
+        let mut unique = UniqueArc::new((1_u64, 2_u64));
+        unique.1 = 3;
+        let second = Arc::map(UniqueArc::into_arc(unique), |p| &p.1);
+        let whole = Arc::owner_downcast::<(u64, u64)>(&second).unwrap();
+        assert_eq!(*whole, (1, 3));
+
+        static V: u64 = 3;
+        assert!(Arc::owner_downcast::<u64>(&Arc::from_static_ref(&V)).is_none());
+    }
+
+    #[test]
+    pub fn from_unsized_alloc() {
+        let std_str: alloc::sync::Arc<str> = alloc::sync::Arc::from("hello world");
+        let addr = std_str.as_ptr();
+        let p: Arc<str> = std_str.into();
+        assert_eq!(&*p, "hello world");
+        assert_eq!(p.as_ptr(), addr, "value was copied");
+        assert_eq!(Arc::allocation_size(&p), 11);
+        let second = Arc::slice(&p, 6..);
+        assert_eq!(Arc::offset_in_allocation(&second), Some(6));
+
+        let mut p: Arc<[u8]> = alloc::sync::Arc::<[u8]>::from([1_u8, 2, 3]).into();
+        Arc::get_mut(&mut p).unwrap()[0] = 4;
+        assert_eq!(*p, [4, 2, 3]);
+
+        let b: alloc::boxed::Box<dyn core::fmt::Debug + Send + Sync> =
+            alloc::boxed::Box::new(24_u64);
+        let p: Arc<dyn core::fmt::Debug + Send + Sync> = b.into();
+        assert_eq!(alloc::format!("{p:?}"), "24");
+    }
+
+    #[test]
+    pub fn from_vec() {
+        let v = alloc::vec![1_u8, 2, 3, 4];
+        let addr = v.as_ptr();
+        let mut p: Arc<[u8]> = v.into();
+        assert_eq!(p.as_ptr(), addr, "elements were copied");
+        assert_eq!(Arc::allocation_size(&p), 4);
+        Arc::get_mut(&mut p).unwrap()[0] = 5;
+        assert_eq!(*p, [5, 2, 3, 4]);
+        let second = Arc::slice(&p, 2..);
+        assert_eq!(Arc::offset_in_allocation(&second), Some(2));
+
+        let s = alloc::string::String::from("hello");
+        let addr = s.as_ptr();
+        let p = Arc::from_string(s);
+        assert_eq!(p.as_ptr(), addr, "contents were copied");
+        assert_eq!(&*p, "hello");
+
+        let b: alloc::boxed::Box<str> = "boxed".into();
+        let addr = b.as_ptr();
+        let p: Arc<str> = b.into();
+        assert_eq!(p.as_ptr(), addr, "contents were copied");
+    }
+
+    #[test]
+    pub fn from_header_and_iter() {
+        let p = Arc::from_header_and_iter(
+            alloc::string::String::from("head"),
+            (1..4_u16).map(|v| v * 2),
+        );
+        let header = Arc::clone_map(&p, |v| v.header.as_str());
+        let slice = Arc::clone_map(&p, |v| &v.slice);
+        drop(p);
+        assert_eq!(&*header, "head");
+        assert_eq!(*slice, [2, 4, 6]);
+        assert!(Arc::same_allocation(&header, &slice));
+        assert!(Arc::offset_in_allocation(&slice).is_some());
+
+        let empty = Arc::from_header_and_iter((), core::iter::empty::<()>());
+        assert_eq!(empty.slice.len(), 0);
+
+        let collected: Arc<[u32]> = (1..=3).collect();
+        assert_eq!(*collected, [1, 2, 3]);
+
+        // The header, the elements and the counts share one allocation.
+        let tracker = alloc::sync::Arc::new(());
+        let p =
+            Arc::from_header_and_iter(tracker.clone(), core::iter::repeat_n(tracker.clone(), 3));
+        assert_eq!(alloc::sync::Arc::strong_count(&tracker), 5);
+        let mut slice = Arc::clone_map(&p, |v| &v.slice);
+        let weak = Arc::downgrade(&slice);
+        assert_eq!(Arc::strong_count(&p), 2);
+        assert_eq!(Arc::weak_count(&p), 1);
+        assert!(Arc::get_mut(&mut slice).is_none());
+        drop(p);
+        assert!(weak.upgrade().is_some());
+        drop(slice);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(), 0);
+        assert_eq!(alloc::sync::Arc::strong_count(&tracker), 1);
+        drop(weak);
+
+        // An iterator whose length isn't known up front is collected first.
+        let mut collected: Arc<[alloc::sync::Arc<()>]> = core::iter::repeat_n(tracker.clone(), 4)
+            .filter(|_| true)
+            .collect();
+        assert_eq!(collected.len(), 4);
+        assert!(Arc::get_mut(&mut collected).is_some());
+        assert_eq!(alloc::sync::Arc::strong_count(&tracker), 5);
+        drop(collected);
+        assert_eq!(alloc::sync::Arc::strong_count(&tracker), 1);
+    }
+
+    /// An iterator that reports a fixed length regardless of how many items
+    /// it actually produces.
+    struct WrongLen(core::ops::Range<u32>, usize);
+
+    impl Iterator for WrongLen {
+        type Item = u32;
+
+        fn next(&mut self) -> Option<u32> {
+            self.0.next()
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (self.1, Some(self.1))
+        }
+    }
+
+    impl ExactSizeIterator for WrongLen {}
+
+    #[test]
+    pub fn from_iter_wrong_len() {
+        let collected: Arc<[u32]> = WrongLen(1..6, 3).collect();
+        assert_eq!(*collected, [1, 2, 3, 4, 5]);
+        let collected: Arc<[u32]> = WrongLen(1..3, 4).collect();
+        assert_eq!(*collected, [1, 2]);
+        let collected: Arc<[u32]> = WrongLen(1..1, 0).collect();
+        assert_eq!(collected.len(), 0);
+    }
+
+    #[test]
+    pub fn inline_threads() {
+        extern crate std;
+        use alloc::sync::Arc as StdArc;
+        use std::thread;
+
+        let tracker = StdArc::new(());
+        let p: Arc<[StdArc<()>]> = core::iter::repeat_n(tracker.clone(), 4).collect();
+        let weak = Arc::downgrade(&p);
+        let threads: alloc::vec::Vec<_> = (0..8)
+            .map(|_| {
+                let p = p.clone();
+                let weak = weak.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let q = p.clone();
+                        let w = Arc::downgrade(&q);
+                        let r = weak.upgrade().unwrap();
+                        assert_eq!(r.len(), 4);
+                        drop((q, r));
+                        assert!(w.upgrade().is_some());
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let mut p = p;
+        assert_eq!(Arc::strong_count(&p), 1);
+        assert_eq!(Arc::weak_count(&p), 1);
+        assert!(Arc::get_mut(&mut p).is_none());
+        drop(weak);
+        assert!(Arc::get_mut(&mut p).is_some());
+        drop(p);
+        assert_eq!(StdArc::strong_count(&tracker), 1);
+
+        // Race dropping the last strong pointer against upgrading, and checking
+        // for uniqueness against downgrading.
+        for _ in 0..100 {
+            let mut p: Arc<[StdArc<()>]> = core::iter::repeat_n(tracker.clone(), 2).collect();
+            let q = p.clone();
+            let weak = Arc::downgrade(&p);
+            let downgrader = thread::spawn(move || {
+                let w = Arc::downgrade(&q);
+                drop(q);
+                w
+            });
+            let upgrader = thread::spawn(move || while weak.upgrade().is_some() {});
+            while Arc::get_mut(&mut p).is_none() && !downgrader.is_finished() {}
+            let w = downgrader.join().unwrap();
+            assert!(Arc::get_mut(&mut p).is_none());
+            drop(p);
+            upgrader.join().unwrap();
+            assert!(w.upgrade().is_none());
+            assert_eq!(w.strong_count(), 0);
+            assert_eq!(StdArc::strong_count(&tracker), 1);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "different number of items")]
+    pub fn from_header_and_iter_too_many() {
+        Arc::from_header_and_iter((), WrongLen(1..6, 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "different number of items")]
+    pub fn from_header_and_iter_too_few() {
+        Arc::from_header_and_iter((), WrongLen(1..3, 4));
+    }
+
+    #[test]
+    pub fn new_uninit() {
+        let mut p = Arc::<alloc::string::String>::new_uninit();
+        Arc::get_mut(&mut p).unwrap().write("hello".into());
+        let p = unsafe { Arc::assume_init(p) };
+        assert_eq!(*p, "hello");
+        assert!(Arc::owner_downcast::<alloc::string::String>(&p).is_some());
+
+        let p = unsafe { Arc::assume_init(Arc::<u64>::new_zeroed()) };
+        assert_eq!(*p, 0);
+
+        let mut p = Arc::<alloc::string::String>::new_uninit_slice(2);
+        for (i, elem) in Arc::get_mut(&mut p).unwrap().iter_mut().enumerate() {
+            elem.write(alloc::format!("{i}"));
+        }
+        let p = unsafe { Arc::assume_init_slice(p) };
+        assert_eq!(*p, ["0", "1"]);
+        assert!(Arc::offset_in_allocation(&p).is_some());
+
+        // The elements are dropped only once they have been marked as initialized.
+        let tracker = alloc::sync::Arc::new(());
+        let mut p = Arc::<alloc::sync::Arc<()>>::new_uninit_slice(2);
+        for elem in Arc::get_mut(&mut p).unwrap() {
+            elem.write(tracker.clone());
+        }
+        let p = unsafe { Arc::assume_init_slice(p) };
+        assert_eq!(alloc::sync::Arc::strong_count(&tracker), 3);
+        drop(p);
+        assert_eq!(alloc::sync::Arc::strong_count(&tracker), 1);
+        drop(Arc::<alloc::sync::Arc<()>>::new_uninit_slice(2));
+
+        // Another pointer to the allocation keeps the elements uninitialized.
+        let mut p = Arc::<alloc::sync::Arc<()>>::new_uninit_slice(1);
+        Arc::get_mut(&mut p).unwrap()[0].write(tracker.clone());
+        let mut q = p.clone();
+        drop(unsafe { Arc::assume_init_slice(p) });
+        assert_eq!(alloc::sync::Arc::strong_count(&tracker), 2);
+        unsafe { Arc::get_mut(&mut q).unwrap()[0].assume_init_drop() };
+        drop(q);
+        assert_eq!(
+            alloc::sync::Arc::strong_count(&tracker),
+            1,
+            "elements were dropped twice"
+        );
+    }
+
+    #[cfg(feature = "experimental_allocator_api")]
+    #[test]
+    pub fn new_in() {
+        use alloc::alloc::{AllocError, Allocator, Global, Layout};
+        use core::ptr::NonNull;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static LIVE: AtomicUsize = AtomicUsize::new(0);
+        static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+        struct Counting;
+        unsafe impl Allocator for Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                LIVE.fetch_add(1, Ordering::Relaxed);
+                LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+                Global.allocate(layout)
+            }
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                LIVE.fetch_sub(1, Ordering::Relaxed);
+                LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+                unsafe { Global.deallocate(ptr, layout) }
+            }
+        }
+
+        let p = Arc::new_in((1_u64, 2_u64), Counting);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 1);
+        assert!(
+            LIVE_BYTES.load(Ordering::Relaxed) > 16 + 2 * core::mem::size_of::<usize>(),
+            "reference counts are not in the allocator"
+        );
+        let second = Arc::map(p, |v| &v.1);
+        assert_eq!(*second, 2);
+        assert_eq!(Arc::offset_in_allocation(&second), Some(8));
+        let third = Arc::try_new_in(3_u64, Counting).unwrap();
+        assert_eq!(LIVE.load(Ordering::Relaxed), 2);
+        let weak = Arc::downgrade(&second);
+        drop((second, third));
+        assert_eq!(LIVE.load(Ordering::Relaxed), 1);
+        assert!(weak.upgrade().is_none());
+        drop(weak);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+        assert_eq!(LIVE_BYTES.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    pub fn unsized_traits() {
+        use alloc::borrow::Cow;
+        let p: Arc<str> = "hello".into();
+        let set: alloc::collections::BTreeSet<Arc<str>> =
+            [p.clone(), "world".into()].into_iter().collect();
+        assert!(set.contains("hello"));
+        assert!(p == "hello");
+        assert!(p == *"hello");
+        assert_eq!(AsRef::<str>::as_ref(&p), "hello");
+
+        let borrowed: Arc<str> = Cow::Borrowed("static").into();
+        assert!(Arc::target_is_static(&borrowed));
+        let owned: Arc<str> = Cow::<'static, str>::Owned("owned".into()).into();
+        assert_eq!(owned, "owned");
+        assert!(!Arc::target_is_static(&owned));
+
+        let s: Arc<[u8]> = [1_u8, 2, 3].as_slice().into();
+        assert!(s == *[1_u8, 2, 3].as_slice());
+        let mut sum = 0;
+        for v in &s {
+            sum += v;
+        }
+        assert_eq!(sum, 6);
+    }
+
+    #[test]
+    pub fn debug_info() {
+        let p = Arc::new((1_u32, 2_u32));
+        let second = Arc::clone_map(&p, |v| &v.1);
+        assert_eq!(alloc::format!("{second:p}"), alloc::format!("{:p}", &p.1));
+        let info = alloc::format!("{:?}", Arc::debug_info(&second));
+        let value = alloc::format!("allocation_value: {:p},", &*p);
+        assert!(info.contains(&value), "{info}");
+        assert!(info.contains("allocation_size: 8"), "{info}");
+        assert!(info.contains("allocation_type: (u32, u32)"), "{info}");
+        assert!(info.contains("offset: Some(4)"), "{info}");
+        assert!(info.contains("strong_count: 2"), "{info}");
+
+        static V: u32 = 1;
+        let info = alloc::format!("{:?}", Arc::debug_info(&Arc::from_static_ref(&V)));
+        assert!(info.contains("is_static: true"), "{info}");
+    }
+
+    #[test]
+    pub fn scoped() {
+        struct Parsed<'a> {
+            words: alloc::vec::Vec<&'a str>,
+        }
+        let buf = alloc::string::String::from("hello scoped world");
+        let parsed = ScopedArc::new(Parsed {
+            words: buf.split(' ').collect(),
+        });
+        let second: ScopedArc<str> = ScopedArc::clone_map(&parsed, |p| p.words[1]);
+        let weak = ScopedArc::downgrade(&second);
+        assert_eq!(&*second, "scoped");
+        assert!(ScopedArc::same_allocation(&second, &parsed));
+        assert_eq!(ScopedArc::strong_count(&parsed), 2);
+        drop(parsed);
+        assert_eq!(&*weak.upgrade().unwrap(), "scoped");
+        drop(second);
+        assert!(weak.upgrade().is_none());
+
+        let p = Arc::new((1_u64, 2_u64));
+        let s: ScopedArc<u64> = Arc::map(p, |v| &v.1).into();
+        assert_eq!(*s, 2);
+
+        let local = 5_u64;
+        let r = ScopedArc::from_ref(&local);
+        assert!(ScopedArc::target_is_borrowed(&r));
+        assert_eq!(*r, 5);
+    }
+
+    #[test]
+    pub fn typed() {
+        struct Pair {
+            a: u64,
+            b: alloc::string::String,
+        }
+        let p = TypedArc::new(Pair {
+            a: 1,
+            b: "two".into(),
+        });
+        assert_eq!(
+            core::mem::size_of::<TypedArc<Pair, u64>>(),
+            2 * core::mem::size_of::<usize>()
+        );
+        let b: TypedArc<Pair, str> = TypedArc::clone_map(&p, |p| p.b.as_str());
+        assert_eq!(&*b, "two");
+        assert_eq!(TypedArc::owner(&b).a, 1);
+        assert!(TypedArc::same_allocation(&b, &p));
+        assert_eq!(TypedArc::strong_count(&p), 2);
+
+        let erased: Arc<str> = b.into();
+        assert_eq!(&*erased, "two");
+        assert!(Arc::owner_downcast::<Pair>(&erased).is_some());
+        assert_eq!(TypedArc::strong_count(&p), 2);
+    }
+
+    #[test]
+    pub fn coerce() {
+        use core::fmt::Debug;
+        let p = Arc::new(24_u64);
+        let d: Arc<dyn Debug> = crate::coerce!(p);
+        assert_eq!(alloc::format!("{d:?}"), "24");
+
+        let a = Arc::new([1_u8, 2, 3]);
+        let s: Arc<[u8]> = crate::coerce!(a);
+        assert_eq!(s.len(), 3);
+    }
+
+    #[cfg(feature = "experimental_coerce_unsized")]
+    #[test]
+    pub fn coerce_unsized() {
+        use core::fmt::Debug;
+        let p = Arc::new(24_u64);
+        let d: Arc<dyn Debug> = p;
+        assert_eq!(alloc::format!("{d:?}"), "24");
+    }
 }
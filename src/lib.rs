@@ -8,12 +8,19 @@
 //! live.
 #![no_std]
 #![cfg_attr(feature = "experimental_allocator_api", feature(allocator_api))]
+#![cfg_attr(
+    feature = "experimental_coerce_unsized",
+    feature(coerce_unsized, unsize)
+)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
 extern crate alloc;
 
 mod macroed;
 
+#[doc(hidden)]
+pub use macroed::Coerce;
+
 #[cfg(feature = "arc")]
 pub mod arc;
 #[cfg(feature = "rc")]
@@ -9,16 +9,35 @@ use alloc::sync::Arc as AllocArc;
 #[cfg(feature = "arc")]
 use alloc::sync::Weak as AllocArcWeak;
 
+/// The part of the documentation of `into_inner` that differs between the
+/// single-threaded and thread-safe pointers.
+#[allow(unused)]
+macro_rules! into_inner_guarantee {
+    (rc) => {
+        "[`Self::try_unwrap`] except that it's guaranteed that if this function is called in turn on every strong pointer to an allocation, each of which targets the entire allocation, then exactly one of the calls will get the target value."
+    };
+    (arc) => {
+        "[`Self::try_unwrap`] except that it's guaranteed that if this function is called on every strong pointer to an allocation, each of which targets the entire allocation, then exactly one of the calls will get the target value, even if the calls are made concurrently on different threads."
+    };
+}
+
 #[allow(unused)]
 macro_rules! rc_wrapper {
-    ($modname: ident, $strongname:ident, $weakname:ident, $basestrong:ident, $baseweak:ident) => {
+    ($modname: ident, $strongname:ident, $weakname:ident, $uniquename:ident, $scopedname:ident, $typedname:ident, $intoname:ident, $basestrong:ident, $baseweak:ident, $count:ty, [$($bound:ident),*]) => {
         pub mod $modname {
             use super::$basestrong;
             use super::$baseweak;
-            use super::Erased;
+            use super::{Cloneable, CountOps, Indirect, IndirectOwner, Plain, Unique};
+            pub use super::HeaderSlice;
+            use core::any::TypeId;
+            use core::ptr::NonNull;
             #[allow(unused)]
             use core::mem::MaybeUninit;
 
+            /// The kind of allocation that this crate makes itself, with counts that
+            /// are atomic only if the pointers can be shared between threads.
+            type Inline = super::Inline<$count>;
+
             #[doc = concat!("Smart pointer to all or part of a reference-counted heap allocation.
 
 This behaves the same as [`", stringify!($basestrong), "`] but has a different internal
@@ -35,16 +54,87 @@ over what it points at regardless of what it's contained within.
 ")]
             pub struct $strongname<T: ?Sized> {
                 ptr: *const T,
-                owner: Option<$basestrong<dyn Erased>>,
+                owner: Option<Owner>,
             }
 
-            impl<T: 'static> $strongname<T> {
+            impl<T: 'static $(+ $bound)*> $strongname<T> {
                 /// Creates a new reference-counted allocation containing the given value.
                 #[inline(always)]
                 pub fn new(v: T) -> Self {
-                    let owner = $basestrong::new(v);
-                    let ptr = $basestrong::as_ptr(&owner);
-                    Self { ptr, owner: Some(owner) }
+                    let alloc = $basestrong::new(Plain(v));
+                    let ptr = $basestrong::as_ptr(&alloc).cast::<T>();
+                    Self { ptr, owner: Some(erase(alloc)) }
+                }
+
+                /// Creates a new reference-counted allocation containing the given value,
+                /// remembering that the value can be cloned.
+                ///
+                /// Pointers derived from the result can then use [`Self::make_mut`] to
+                /// obtain a mutable reference to their target even when the allocation is
+                /// shared, by cloning the entire allocation.
+                ///
+                /// # Safety
+                ///
+                /// [`Self::make_mut`] re-derives the pointer at the same offset within the
+                /// clone, so the implementation of [`Clone`] for `T` must always produce a
+                /// value with the same layout as the original: every part of the value
+                /// must have the same type at the same offset, and so any enum within the
+                /// value must keep the same variant. This is true for an implementation
+                /// derived with `#[derive(Clone)]` on a type whose fields all also meet
+                /// this requirement, but not for an implementation that might return a
+                /// different enum variant.
+                #[inline(always)]
+                pub unsafe fn new_cloneable(v: T) -> Self
+                where
+                    T: Clone,
+                {
+                    let alloc = $basestrong::new(Cloneable(v));
+                    let ptr = $basestrong::as_ptr(&alloc).cast::<T>();
+                    Self { ptr, owner: Some(erase(alloc)) }
+                }
+
+                /// Creates a new reference-counted allocation suitable for `T` without
+                /// initializing it.
+                ///
+                /// The target can be initialized through [`Self::get_mut`] while the
+                /// allocation is not yet shared, and then converted into a pointer to
+                #[doc = concat!("an initialized `T` using [`", stringify!($strongname), "::assume_init`].")]
+                #[inline(always)]
+                pub fn new_uninit() -> $strongname<MaybeUninit<T>> {
+                    $strongname::from_alloc($basestrong::<T>::new_uninit())
+                }
+
+                /// Creates a new reference-counted allocation suitable for `T`, with
+                /// all of its bytes set to zero.
+                ///
+                /// Whether that is a valid `T` depends on the type; see
+                /// [`MaybeUninit::zeroed`] for more information.
+                #[inline(always)]
+                pub fn new_zeroed() -> $strongname<MaybeUninit<T>> {
+                    $strongname::from_alloc($basestrong::<T>::new_zeroed())
+                }
+
+                /// Creates a new allocation suitable for a slice of `len` elements of
+                /// type `T`, without initializing them.
+                ///
+                /// The elements can be initialized through [`Self::get_mut`] while the
+                /// allocation is not yet shared, and then converted into a pointer to
+                #[doc = concat!("an initialized slice using [`", stringify!($strongname), "::assume_init_slice`].")]
+                /// The elements are stored in the same allocation as the reference counts.
+                pub fn new_uninit_slice(len: usize) -> $strongname<[MaybeUninit<T>]> {
+                    let Ok((header, ptr)) = super::new_inline::<$count, [T], (), MaybeUninit<T>>(
+                        (),
+                        len,
+                        &mut (0..len).map(|_| MaybeUninit::uninit()),
+                        super::drop_header_slice::<(), T>,
+                        false,
+                    ) else {
+                        unreachable!("a range reports its exact length");
+                    };
+                    $strongname {
+                        ptr: ptr as *const [MaybeUninit<T>],
+                        owner: Some(Owner { ptr: header }),
+                    }
                 }
 
                 /// Constructs a new reference-counted allocation that could contain a weak pointer to itself.
@@ -54,48 +144,220 @@ over what it points at regardless of what it's contained within.
                 /// allocation. The result may contain zero or more clones of the weak reference, which
                 /// then become valid once `new_cyclic` returns.
                 pub fn new_cyclic(data_fn: impl FnOnce(&$weakname<T>) -> T) -> Self {
-                    let owner = $basestrong::new_cyclic(|alloc_weak| {
-                        let weak = $weakname::from_alloc($baseweak::clone(alloc_weak));
-                        data_fn(&weak)
+                    let alloc = $basestrong::new_cyclic(|alloc_weak: &$baseweak<Plain<T>>| {
+                        let weak = $weakname {
+                            ptr: NonNull::new($baseweak::as_ptr(alloc_weak).cast::<T>().cast_mut()),
+                            owner: Some(erase_weak(alloc_weak.clone())),
+                        };
+                        Plain(data_fn(&weak))
+                    });
+                    let ptr = $basestrong::as_ptr(&alloc).cast::<T>();
+                    Self { ptr, owner: Some(erase(alloc)) }
+                }
+
+                /// Constructs a new reference-counted allocation that could contain a weak pointer
+                /// to itself, using a closure that might fail.
+                ///
+                /// This behaves in the same way as [`Self::new_cyclic`] except that if the closure
+                /// returns an error then the allocation is released and the error is returned.
+                /// Any clones of the weak reference made by the closure then remain permanently
+                /// impossible to upgrade.
+                pub fn try_new_cyclic<E>(data_fn: impl FnOnce(&$weakname<T>) -> Result<T, E>) -> Result<Self, E> {
+                    let mut err = None;
+                    let alloc = $basestrong::new_cyclic(|alloc_weak: &$baseweak<Plain<MaybeUninit<T>>>| {
+                        // Plain<MaybeUninit<T>> has the same layout as Plain<T>, and the weak
+                        // reference cannot be upgraded until the value has been initialized.
+                        let alloc_weak = unsafe { $baseweak::from_raw($baseweak::into_raw(alloc_weak.clone()).cast::<Plain<T>>()) };
+                        let weak = $weakname {
+                            ptr: NonNull::new($baseweak::as_ptr(&alloc_weak).cast::<T>().cast_mut()),
+                            owner: Some(erase_weak(alloc_weak)),
+                        };
+                        match data_fn(&weak) {
+                            Ok(v) => Plain(MaybeUninit::new(v)),
+                            Err(e) => {
+                                err = Some(e);
+                                Plain(MaybeUninit::uninit())
+                            }
+                        }
                     });
-                    let ptr = $basestrong::as_ptr(&owner);
-                    Self { ptr, owner: Some(owner) }
+                    if let Some(err) = err {
+                        // Dropping the only strong reference does not drop the uninitialized
+                        // value, and any remaining weak references can no longer be upgraded.
+                        return Err(err);
+                    }
+                    // SAFETY: The value was initialized by the closure.
+                    let alloc = unsafe { $basestrong::from_raw($basestrong::into_raw(alloc).cast::<Plain<T>>()) };
+                    let ptr = $basestrong::as_ptr(&alloc).cast::<T>();
+                    Ok(Self { ptr, owner: Some(erase(alloc)) })
                 }
 
                 #[doc = concat!("Transforms an [`", stringify!($basestrong) ,"`] into an [`", stringify!($strongname), "`] referring to the same allocation.")]
+                ///
+                /// For a dynamically-sized `T`, use [`Self::from_unsized_alloc`] instead.
                 #[inline(always)]
                 pub fn from_alloc(v: $basestrong<T>) -> Self {
-                    // TODO: It would be better if this were implemented for ?Sized
-                    // but we can't type-erase a dynamically-sized T.
-                    Self {
-                        ptr: $basestrong::as_ptr(&v),
-                        owner: Some(v as $basestrong<dyn Erased>),
+                    let ptr = $basestrong::as_ptr(&v);
+                    Self { ptr, owner: Some(erase(plain_alloc(v))) }
+                }
+
+                #[doc = concat!("Transforms an [`", stringify!($strongname), "`] into an [`", stringify!($basestrong) ,"`] referring to the same allocation, if possible.
+
+This is possible only if the target of `this` is an entire allocation of type `T`, such
+as the result of [`Self::new`] or [`Self::from_alloc`]. Otherwise, returns `this`
+unchanged.")]
+                pub fn try_into_alloc(this: Self) -> Result<$basestrong<T>, Self> {
+                    if !Self::targets_whole_alloc(&this) {
+                        return Err(this);
+                    }
+                    let Some(owner) = this.owner else {
+                        unreachable!("static targets are never whole allocations");
+                    };
+                    // targets_whole_alloc checked that the allocation has the same
+                    // layout as a T.
+                    let raw = Owner::into_raw(owner);
+                    Ok(unsafe { $basestrong::from_raw(raw.as_ptr().cast_const().cast::<T>()) })
+                }
+
+                /// Returns the target value if `this` is the only strong pointer to an
+                /// allocation of type `T` and targets that entire allocation.
+                ///
+                /// Otherwise, returns `this` unchanged. This can succeed only for pointers
+                /// that refer to an entire allocation, such as the result of [`Self::new`]
+                /// or [`Self::from_alloc`], and never for a pointer into a static object
+                /// created with [`Self::from_static_ref`].
+                pub fn try_unwrap(this: Self) -> Result<T, Self> {
+                    if !Self::targets_whole_alloc(&this) {
+                        return Err(this);
+                    }
+                    let Some(owner) = this.owner else {
+                        unreachable!("static targets are never whole allocations");
+                    };
+                    let raw = Owner::into_raw(owner);
+                    let alloc = unsafe { $basestrong::from_raw(raw.as_ptr().cast_const().cast::<T>()) };
+                    $basestrong::try_unwrap(alloc).map_err(|alloc| {
+                        // We must restore the original vtable so that the allocation
+                        // retains its capabilities.
+                        let _ = $basestrong::into_raw(alloc);
+                        let owner = unsafe { Owner::from_raw(raw) };
+                        Self { ptr: this.ptr, owner: Some(owner) }
+                    })
+                }
+
+                /// Returns the target value if `this` is the only strong pointer to an
+                /// allocation of type `T` and targets that entire allocation.
+                ///
+                /// Otherwise, drops `this` and returns `None`. This is similar to
+                #[doc = into_inner_guarantee!($modname)]
+                /// The guarantee doesn't hold if any of the pointers targets only part of
+                /// the allocation, or was converted to some other target type, because
+                /// such a pointer never gets the value and so none of the calls might.
+                pub fn into_inner(this: Self) -> Option<T> {
+                    Self::try_into_alloc(this).ok().and_then($basestrong::into_inner)
+                }
+
+                /// Returns the target value if possible, or otherwise a clone of it.
+                ///
+                /// This uses [`Self::try_unwrap`] to move the value out of the allocation
+                /// if possible, and clones the target otherwise.
+                pub fn unwrap_or_clone(this: Self) -> T
+                where
+                    T: Clone,
+                {
+                    Self::try_unwrap(this).unwrap_or_else(|this| (*this).clone())
+                }
+
+                /// Returns `true` if `this` refers to an entire allocation of type `T`.
+                fn targets_whole_alloc(this: &Self) -> bool {
+                    match &this.owner {
+                        Some(owner) => {
+                            owner.is_transparent()
+                                && owner.value_type_id() == TypeId::of::<T>()
+                                && core::ptr::addr_eq(this.ptr, Owner::as_ptr(owner))
+                        }
+                        None => false,
                     }
                 }
             }
 
+            impl<T: ?Sized + 'static $(+ $bound)*> $strongname<T> {
+                #[doc = concat!("Transforms a possibly-dynamically-sized [`", stringify!($basestrong) ,"`] into an [`", stringify!($strongname), "`] referring to the same allocation.
+
+A dynamically-sized allocation, such as a string slice or a trait object, cannot
+be type-erased directly, so this makes a small additional allocation to hold
+the original pointer. The value itself is not copied. For a sized `T`,
+[`Self::from_alloc`] avoids the additional allocation.")]
+                pub fn from_unsized_alloc(v: $basestrong<T>) -> Self {
+                    Self::from_indirect(v)
+                }
+
+                /// Transforms a [`Box`](alloc::boxed::Box) into a reference-counted
+                /// pointer to the same allocation, without copying the value.
+                ///
+                /// This works for dynamically-sized values such as trait objects, and
+                /// makes a small additional allocation to hold the original pointer in
+                /// the same way as [`Self::from_unsized_alloc`].
+                pub fn from_box(v: alloc::boxed::Box<T>) -> Self {
+                    Self::from_indirect(v)
+                }
+
+                fn from_indirect<P: IndirectOwner<Target = T> + 'static $(+ $bound)*>(v: P) -> Self {
+                    let alloc = $basestrong::new(Indirect(v));
+                    let ptr = &*alloc.0 as *const T;
+                    Self { ptr, owner: Some(erase(alloc)) }
+                }
+            }
+
             #[cfg(feature = "experimental_allocator_api")]
             #[cfg_attr(docsrs, doc(cfg(feature = "experimental_allocator_api")))]
             /// Additional functions that are available only with feature `experimental_allocator_api`,
             /// which in turn depends on the Rust experimental feature `allocator_api` and thus
             /// requires a nightly build and is subject to break in future.
-            impl<T: 'static> $strongname<T> {
+            impl<T: 'static $(+ $bound)*> $strongname<T> {
                 /// Creates a new reference-counted allocation containing the given value,
                 /// returning an error if the allocation fails.
                 #[inline(always)]
                 pub fn try_new(v: T) -> Result<Self, alloc::alloc::AllocError> {
-                    let owner = $basestrong::try_new(v)?;
-                    let ptr = $basestrong::as_ptr(&owner);
-                    Ok(Self { ptr, owner: Some(owner) })
+                    let alloc = $basestrong::try_new(Plain(v))?;
+                    let ptr = $basestrong::as_ptr(&alloc).cast::<T>();
+                    Ok(Self { ptr, owner: Some(erase(alloc)) })
                 }
 
                 /// Creates a new reference-counted allocation suitable for `T` without
                 /// initializing it, returning an error if the allocation fails.
                 #[inline(always)]
                 pub fn try_new_uninit() -> Result<$strongname<MaybeUninit<T>>, alloc::alloc::AllocError> {
-                    let owner = $basestrong::try_new_uninit()?;
-                    let ptr = $basestrong::as_ptr(&owner);
-                    Ok($strongname { ptr, owner: Some(owner) })
+                    let alloc = $basestrong::<T>::try_new_uninit()?;
+                    let ptr = $basestrong::as_ptr(&alloc);
+                    Ok($strongname { ptr, owner: Some(erase(plain_alloc(alloc))) })
+                }
+
+                /// Creates a new reference-counted allocation containing the given value,
+                /// placing the value in the given allocator.
+                ///
+                /// The result has the same type as a pointer created by [`Self::new`], so
+                /// pointers into values in different allocators can be used interchangeably.
+                /// The reference counts are stored in `alloc` along with the value, and so
+                /// is the allocator itself, which is dropped when the allocation is freed.
+                #[inline(always)]
+                pub fn new_in<A: alloc::alloc::Allocator + 'static $(+ $bound)*>(v: T, alloc: A) -> Self {
+                    match Self::try_new_in(v, alloc) {
+                        Ok(this) => this,
+                        Err(_) => alloc::alloc::handle_alloc_error(super::inline_in_layout::<$count, A, T>().0),
+                    }
+                }
+
+                /// Creates a new reference-counted allocation containing the given value,
+                /// placing the value in the given allocator and returning an error if
+                /// the allocation fails.
+                ///
+                /// See [`Self::new_in`] for more information.
+                #[inline(always)]
+                pub fn try_new_in<A: alloc::alloc::Allocator + 'static $(+ $bound)*>(v: T, alloc: A) -> Result<Self, alloc::alloc::AllocError> {
+                    let (header, ptr) = super::new_inline_in::<$count, T, A>(v, alloc)?;
+                    Ok(Self {
+                        ptr,
+                        owner: Some(Owner { ptr: header }),
+                    })
                 }
             }
 
@@ -117,8 +379,8 @@ over what it points at regardless of what it's contained within.
                 /// allocation, which would therefore remain valid as long as
                 /// that allocation is live.
                 #[inline(always)]
-                pub unsafe fn from_raw_parts<AllocT: 'static>(ptr: *const T, owner: $basestrong<AllocT>) -> Self {
-                    Self { ptr, owner: Some(owner as $basestrong<dyn Erased>) }
+                pub unsafe fn from_raw_parts<AllocT: 'static $(+ $bound)*>(ptr: *const T, owner: $basestrong<AllocT>) -> Self {
+                    Self { ptr, owner: Some(erase(plain_alloc(owner))) }
                 }
 
                 /// Gets a raw pointer to the target.
@@ -134,6 +396,7 @@ over what it points at regardless of what it's contained within.
                 ///
                 /// This increments the reference count for the underlying allocation.
                 #[inline(always)]
+                #[allow(clippy::should_implement_trait)]
                 pub fn clone(this: &Self) -> Self {
                     Self {
                         ptr: this.ptr,
@@ -175,19 +438,251 @@ over what it points at regardless of what it's contained within.
                     })
                 }
 
+                /// Returns a mutable reference to the target if there are no other strong
+                /// or weak pointers to the same allocation.
+                ///
+                /// Returns `None` otherwise, because it isn't safe to mutate a shared value.
+                /// Also returns `None` for a pointer into a static object created with
+                /// [`Self::from_static_ref`], and for a target that isn't stored inside the
+                /// allocation itself, as described for [`Self::offset_in_allocation`].
+                pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+                    let offset = Self::offset_in_allocation(this)?;
+                    let owner = this.owner.as_mut()?;
+                    let base = Owner::get_mut(owner)?.value_mut()?;
+                    // The target pointer was derived from a shared reference, so we
+                    // re-derive it from the unique reference to the whole allocation.
+                    let ptr = super::with_metadata_of(base.wrapping_add(offset), this.ptr);
+                    Some(unsafe { &mut *ptr })
+                }
+
+                /// Returns a mutable reference to the target, first cloning the entire
+                /// allocation if there are any other strong or weak pointers to it.
+                ///
+                /// After cloning, `this` refers to the same offset within the new
+                /// allocation as it previously did within the old one, and the other
+                /// pointers to the old allocation are unaffected. The safety contract
+                /// of [`Self::new_cloneable`] ensures that the same offset in the clone
+                /// holds a value of the same type.
+                ///
+                /// Cloning is possible only if the allocation was created using
+                /// [`Self::new_cloneable`], because otherwise the type of the allocation
+                /// is not known to implement [`Clone`]. If the allocation cannot be
+                /// cloned, or if [`Self::get_mut`] would return `None` for any reason
+                /// other than the allocation being shared, returns `None`.
+                pub fn make_mut(this: &mut Self) -> Option<&mut T> {
+                    let offset = Self::offset_in_allocation(this)?;
+                    let owner = this.owner.as_mut()?;
+                    if Owner::get_mut(owner).is_none() {
+                        let new_owner = owner.clone_alloc()?;
+                        let base = new_owner.value_ptr();
+                        this.ptr = super::with_metadata_of(base.wrapping_add(offset).cast_mut(), this.ptr);
+                        *owner = new_owner;
+                    }
+                    Self::get_mut(this)
+                }
+
+                /// Creates a new pointer to some part of the current pointer's target,
+                /// within the same allocation, consuming the current pointer.
+                ///
+                /// This is like [`Self::clone_map`] except that the strong reference
+                /// held by `this` is transferred to the result, and so the reference
+                /// count of the allocation is not modified.
+                pub fn map<R: ?Sized>(this: Self, f: impl FnOnce(&T) -> &R) -> $strongname<R> {
+                    let r = f(unsafe { &*this.ptr });
+                    $strongname {
+                        ptr: r as *const _,
+                        owner: this.owner,
+                    }
+                }
+
+                /// Conditionally creates a new pointer to some part of the current pointer's
+                /// target, within the same allocation, consuming the current pointer.
+                ///
+                /// This is like [`Self::clone_filter_map`] except that the strong reference
+                /// held by `this` is transferred to the result. If the closure returns `None`
+                /// then the original pointer is returned in the `Err` variant.
+                pub fn filter_map<R: ?Sized>(
+                    this: Self,
+                    f: impl FnOnce(&T) -> Option<&R>,
+                ) -> Result<$strongname<R>, Self> {
+                    match f(unsafe { &*this.ptr }) {
+                        Some(r) => Ok($strongname {
+                            ptr: r as *const _,
+                            owner: this.owner,
+                        }),
+                        None => Err(this),
+                    }
+                }
+
+                /// Fallibly creates a new pointer to some part of the current pointer's
+                /// target, within the same allocation, consuming the current pointer.
+                ///
+                /// This is like [`Self::filter_map`] except that the closure can return
+                /// an error, which is then returned along with the original pointer.
+                pub fn try_map<R: ?Sized, E>(
+                    this: Self,
+                    f: impl FnOnce(&T) -> Result<&R, E>,
+                ) -> Result<$strongname<R>, (Self, E)> {
+                    match f(unsafe { &*this.ptr }) {
+                        Ok(r) => Ok($strongname {
+                            ptr: r as *const _,
+                            owner: this.owner,
+                        }),
+                        Err(e) => Err((this, e)),
+                    }
+                }
+
+                /// Splits the pointer into two pointers to different parts of the current
+                /// pointer's target, within the same allocation, consuming the current pointer.
+                ///
+                /// This is similar to [`core::cell::Ref::map_split`]. The strong reference
+                /// held by `this` is transferred to one of the results and so the reference
+                /// count of the allocation increases by only one.
+                pub fn map_split<A: ?Sized, B: ?Sized>(
+                    this: Self,
+                    f: impl FnOnce(&T) -> (&A, &B),
+                ) -> ($strongname<A>, $strongname<B>) {
+                    let (a, b) = f(unsafe { &*this.ptr });
+                    (
+                        $strongname { ptr: a as *const _, owner: this.owner.clone() },
+                        $strongname { ptr: b as *const _, owner: this.owner },
+                    )
+                }
+
+                /// Splits the pointer into three pointers to different parts of the current
+                /// pointer's target, within the same allocation, consuming the current pointer.
+                ///
+                /// This is the three-part version of [`Self::map_split`].
+                pub fn map_split3<A: ?Sized, B: ?Sized, C: ?Sized>(
+                    this: Self,
+                    f: impl FnOnce(&T) -> (&A, &B, &C),
+                ) -> ($strongname<A>, $strongname<B>, $strongname<C>) {
+                    let (a, b, c) = f(unsafe { &*this.ptr });
+                    (
+                        $strongname { ptr: a as *const _, owner: this.owner.clone() },
+                        $strongname { ptr: b as *const _, owner: this.owner.clone() },
+                        $strongname { ptr: c as *const _, owner: this.owner },
+                    )
+                }
+
+                /// Splits the pointer into four pointers to different parts of the current
+                /// pointer's target, within the same allocation, consuming the current pointer.
+                ///
+                /// This is the four-part version of [`Self::map_split`].
+                #[allow(clippy::type_complexity)]
+                pub fn map_split4<A: ?Sized, B: ?Sized, C: ?Sized, D: ?Sized>(
+                    this: Self,
+                    f: impl FnOnce(&T) -> (&A, &B, &C, &D),
+                ) -> ($strongname<A>, $strongname<B>, $strongname<C>, $strongname<D>) {
+                    let (a, b, c, d) = f(unsafe { &*this.ptr });
+                    (
+                        $strongname { ptr: a as *const _, owner: this.owner.clone() },
+                        $strongname { ptr: b as *const _, owner: this.owner.clone() },
+                        $strongname { ptr: c as *const _, owner: this.owner.clone() },
+                        $strongname { ptr: d as *const _, owner: this.owner },
+                    )
+                }
+
+                /// Creates an iterator over pointers to parts of the current pointer's
+                /// target, within the same allocation.
+                ///
+                /// The function receives a reference to the pointer's target and must
+                /// return a reference to a collection within it, such as the target
+                /// itself or one of its fields, which is then iterated by reference in
+                /// the same way as a `for` loop over `&collection`. Each item of the
+                /// resulting iterator is a new pointer to one of the collection's
+                /// elements, and so each one increments the strong reference count of
+                /// the allocation and can outlive both the iterator and `this`.
+                pub fn iter_map<'a, C, R, F>(this: &'a Self, f: F) -> IterMap<'a, <&'a C as IntoIterator>::IntoIter>
+                where
+                    C: ?Sized + 'a,
+                    R: ?Sized + 'a,
+                    F: for<'x> FnOnce(&'x T) -> &'x C,
+                    for<'x> &'x C: IntoIterator<Item = &'x R>,
+                {
+                    // Safety: The collection is derived from the target, for any
+                    // lifetime of the borrow, and so are the references it yields.
+                    unsafe { Self::iter_map_unchecked(this, |t| f(t).into_iter()) }
+                }
+
+                /// Implementation of [`Self::iter_map`] for callers that return
+                /// iterators which capture other borrows.
+                ///
+                /// # Safety
+                ///
+                /// Every reference produced by the iterator that `f` returns must point
+                /// into the reference that `f` was given.
+                unsafe fn iter_map_unchecked<'a, I, R>(this: &'a Self, f: impl FnOnce(&'a T) -> I) -> IterMap<'a, I>
+                where
+                    I: Iterator<Item = &'a R>,
+                    R: ?Sized + 'a,
+                {
+                    let r = unsafe { &*this.ptr };
+                    IterMap {
+                        iter: f(r),
+                        owner: &this.owner,
+                    }
+                }
+
+                /// Creates a new pointer to a subrange of the current pointer's target,
+                /// within the same allocation.
+                ///
+                /// This is available for any target that can be indexed by a range to
+                /// produce a value of the same type, such as slices and string slices.
+                ///
+                /// # Panics
+                ///
+                /// Panics in the same situations as indexing the target with `index`
+                /// directly, such as when `index` is out of bounds.
+                pub fn slice<I>(this: &Self, index: I) -> Self
+                where
+                    T: core::ops::Index<I, Output = T>,
+                {
+                    Self::clone_map(this, |s| &s[index])
+                }
+
+                /// Creates two new pointers to the parts of the current pointer's target
+                /// before and after the index `mid`, within the same allocation.
+                ///
+                /// This is available for any target that can be indexed by a range to
+                /// produce a value of the same type, such as slices and string slices.
+                ///
+                /// # Panics
+                ///
+                /// Panics in the same situations as the `split_at` method of the
+                /// target type, such as when `mid` is out of bounds.
+                pub fn split_at(this: &Self, mid: usize) -> (Self, Self)
+                where
+                    T: core::ops::Index<core::ops::RangeTo<usize>, Output = T>
+                        + core::ops::Index<core::ops::RangeFrom<usize>, Output = T>,
+                {
+                    Self::map_split(Self::clone(this), |s| (&s[..mid], &s[mid..]))
+                }
+
+                /// Creates a new pointer to the entire allocation that contains the current
+                /// pointer's target, if that allocation's value is of type `A`.
+                ///
+                /// This allows recovering the original value that a pointer was derived
+                /// from, such as the struct that contains a field. Returns `None` if the
+                /// allocation's value is of some other type, or if `this` was created
+                /// using [`Self::from_static_ref`].
+                pub fn owner_downcast<A: 'static>(this: &Self) -> Option<$strongname<A>> {
+                    let owner = this.owner.as_ref()?;
+                    if owner.value_type_id() != TypeId::of::<A>() {
+                        return None;
+                    }
+                    Some($strongname {
+                        ptr: owner.value_ptr().cast::<A>(),
+                        owner: Some(owner.clone()),
+                    })
+                }
+
                 /// Creates a weak pointer to the same target value.
                 pub fn downgrade(this: &Self) -> Weak<T> {
-                    match &this.owner {
-                        Some(owner) => Weak {
-                            ptr: this.ptr,
-                            owner: Some($basestrong::downgrade(owner)),
-                        },
-                        None => Weak {
-                            ptr: this.ptr,
-                            owner: None,
-                        }
+                    Weak {
+                        ptr: NonNull::new(this.ptr.cast_mut()),
+                        owner: this.owner.as_ref().map(Owner::downgrade),
                     }
-
                 }
 
                 /// Gets the number of strong pointers to this allocation.
@@ -198,7 +693,7 @@ over what it points at regardless of what it's contained within.
                 #[inline(always)]
                 pub fn strong_count(this: &Self) -> usize {
                     match &this.owner {
-                        Some(owner) => $basestrong::strong_count(owner),
+                        Some(owner) => Owner::strong_count(owner),
                         None => usize::MAX,
                     }
                 }
@@ -211,7 +706,7 @@ over what it points at regardless of what it's contained within.
                 #[inline(always)]
                 pub fn weak_count(this: &Self) -> usize {
                     match &this.owner {
-                        Some(owner) => $basestrong::weak_count(owner),
+                        Some(owner) => Owner::weak_count(owner),
                         None => usize::MAX,
                     }
                 }
@@ -226,7 +721,7 @@ over what it points at regardless of what it's contained within.
                 #[inline(always)]
                 pub fn allocation_size(this: &Self) -> usize {
                     match &this.owner {
-                        Some(owner) => core::mem::size_of_val(core::ops::Deref::deref(owner)),
+                        Some(owner) => owner.value_size(),
                         None => 0,
                     }
                 }
@@ -237,6 +732,333 @@ over what it points at regardless of what it's contained within.
                 pub fn target_is_static(this: &Self) -> bool {
                     this.owner.is_none()
                 }
+
+                /// Returns a value whose [`Debug`](core::fmt::Debug) implementation describes
+                /// the pointer and the allocation it refers to, for diagnostic purposes.
+                ///
+                /// This includes the address of the target, the address, size, and type
+                /// name of the value stored in the allocation, the offset of the target
+                /// within that value, and the reference counts.
+                #[inline(always)]
+                pub fn debug_info(this: &Self) -> DebugInfo<'_, T> {
+                    DebugInfo(this)
+                }
+
+                /// Consumes the pointer and returns a reference to the target that is
+                /// valid for the rest of the program, by intentionally leaking the
+                /// strong reference to the allocation.
+                ///
+                /// The allocation will therefore never be freed. For a pointer into a
+                /// static object created with [`Self::from_static_ref`], this just
+                /// returns the original static reference.
+                pub fn leak(this: Self) -> &'static T
+                where
+                    T: 'static,
+                {
+                    core::mem::forget(this.owner);
+                    unsafe { &*this.ptr }
+                }
+
+                /// Returns `true` if the two pointers refer to the same address.
+                ///
+                /// This compares only the addresses of the targets, ignoring any metadata
+                /// such as slice lengths, in the same way as [`core::ptr::addr_eq`]. Two
+                /// pointers to zero-sized values, or to a struct and its first field,
+                /// might therefore compare equal even though they refer to different
+                /// objects.
+                #[inline(always)]
+                pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+                    core::ptr::addr_eq(this.ptr, other.ptr)
+                }
+
+                /// Returns `true` if the two pointers refer to targets within the same
+                /// reference-counted allocation, even if the targets themselves differ.
+                ///
+                /// Always returns `false` if either pointer was created using
+                /// [`Self::from_static_ref`], because static objects are not
+                /// reference-counted allocations.
+                #[inline(always)]
+                pub fn same_allocation<U: ?Sized>(this: &Self, other: &$strongname<U>) -> bool {
+                    match (&this.owner, &other.owner) {
+                        (Some(a), Some(b)) => Owner::ptr_eq(a, b),
+                        _ => false,
+                    }
+                }
+
+                /// Gets the offset in bytes of the target from the start of the allocation
+                /// that contains it.
+                ///
+                /// Returns `None` for a pointer into a static object created with
+                /// [`Self::from_static_ref`], and for a target that isn't actually stored
+                /// inside the allocation, such as the elements of a `Vec` that the
+                /// allocation contains.
+                pub fn offset_in_allocation(this: &Self) -> Option<usize> {
+                    let owner = this.owner.as_ref()?;
+                    let start = owner.value_ptr().addr();
+                    let offset = this.ptr.cast::<u8>().addr().checked_sub(start)?;
+                    let size = core::mem::size_of_val(unsafe { &*this.ptr });
+                    if offset + size <= Self::allocation_size(this) {
+                        Some(offset)
+                    } else {
+                        None
+                    }
+                }
+
+                /// Gets the range of bytes that the target of `this` occupies within the
+                /// target of `parent`, such as the span of a substring within the string
+                /// it was derived from.
+                ///
+                /// Returns `None` if the two pointers don't belong to the same allocation,
+                /// as decided by [`Self::same_allocation`], or if the target of `this` does
+                /// not lie entirely within the target of `parent`. Pointers into static
+                /// objects are considered only by their addresses, since static objects
+                /// are not reference-counted allocations.
+                pub fn byte_range_within<U: ?Sized>(
+                    this: &Self,
+                    parent: &$strongname<U>,
+                ) -> Option<core::ops::Range<usize>> {
+                    let both_static = this.owner.is_none() && parent.owner.is_none();
+                    if !(both_static || Self::same_allocation(this, parent)) {
+                        return None;
+                    }
+                    let parent_start = parent.ptr.cast::<u8>().addr();
+                    let parent_size = core::mem::size_of_val(unsafe { &*parent.ptr });
+                    let start = this.ptr.cast::<u8>().addr().checked_sub(parent_start)?;
+                    let end = start + core::mem::size_of_val(unsafe { &*this.ptr });
+                    if end <= parent_size {
+                        Some(start..end)
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            /// Additional functions for pointers to slices.
+            impl<T: 'static $(+ $bound)*> $strongname<[T]> {
+                /// Adopts the buffer of the given vector as the target, without
+                /// copying its elements.
+                ///
+                #[doc = concat!("Unlike the standard library's `", stringify!($basestrong), "<[T]>::from(Vec<T>)`, the elements stay in the vector's own buffer and the vector is kept alive as the owner of the allocation. This makes a small additional allocation to hold the vector itself, and any excess capacity remains allocated until the last strong pointer is dropped.")]
+                pub fn from_vec(v: alloc::vec::Vec<T>) -> Self {
+                    Self::from_indirect(v)
+                }
+
+                /// Allocates a slice of `len` elements taken from `iter`, in the same
+                /// allocation as the reference counts.
+                ///
+                /// If `iter` produces some other number of items then this instead
+                /// returns the items taken so far, leaving the rest in `iter`.
+                fn try_from_iter_len(len: usize, iter: &mut impl Iterator<Item = T>) -> Result<Self, alloc::vec::Vec<T>> {
+                    match super::new_inline::<$count, [T], (), T>((), len, iter, super::drop_header_slice::<(), T>, true) {
+                        Ok((header, ptr)) => Ok(Self {
+                            ptr: ptr as *const [T],
+                            owner: Some(Owner { ptr: header }),
+                        }),
+                        Err(((), items)) => Err(items),
+                    }
+                }
+
+                /// Allocates a slice of `len` elements taken from `iter`, in the same
+                /// allocation as the reference counts.
+                ///
+                /// Panics if `iter` doesn't produce exactly `len` items.
+                fn from_exact_iter(len: usize, mut iter: impl Iterator<Item = T>) -> Self {
+                    match Self::try_from_iter_len(len, &mut iter) {
+                        Ok(this) => this,
+                        Err(_) => panic!("iterator produced a different number of items than it reported"),
+                    }
+                }
+            }
+
+            impl<H: 'static $(+ $bound)*, T: 'static $(+ $bound)*> $strongname<HeaderSlice<H, [T]>> {
+                /// Allocates a header followed by a slice filled from the given iterator,
+                /// storing the two together in a single allocation.
+                ///
+                /// The header and the slice can then be projected into separate pointers
+                /// using [`Self::clone_map`], which both keep the whole allocation live.
+                ///
+                /// The reference counts are stored in the same allocation.
+                ///
+                /// # Panics
+                ///
+                /// Panics if the iterator produces a different number of items than it
+                /// reported.
+                pub fn from_header_and_iter<I>(header: H, iter: I) -> Self
+                where
+                    I: IntoIterator<Item = T>,
+                    I::IntoIter: ExactSizeIterator,
+                {
+                    let mut iter = iter.into_iter();
+                    let result = super::new_inline::<$count, HeaderSlice<H, [T]>, H, T>(
+                        header,
+                        iter.len(),
+                        &mut iter,
+                        super::drop_header_slice::<H, T>,
+                        true,
+                    );
+                    match result {
+                        Ok((header, ptr)) => Self {
+                            ptr,
+                            owner: Some(Owner { ptr: header }),
+                        },
+                        Err(_) => panic!("iterator produced a different number of items than it reported"),
+                    }
+                }
+            }
+
+            impl<T: 'static $(+ $bound)*> $strongname<MaybeUninit<T>> {
+                /// Converts into a pointer to the initialized value.
+                ///
+                /// If the target is the entire value of an allocation created by
+                #[doc = concat!("[`", stringify!($strongname), "::new_uninit`] or [`", stringify!($strongname), "::new_zeroed`] then the allocation")]
+                /// becomes responsible for dropping the value once the last strong
+                /// pointer is dropped. Otherwise the allocation continues to treat the
+                /// target as uninitialized, and so the value is never dropped.
+                ///
+                /// Only the returned pointer and the pointers derived from it drop the
+                #[doc = concat!("value, so if another `", stringify!($strongname), "<MaybeUninit<T>>` to the same allocation happens")]
+                /// to be the last strong pointer dropped then the value is leaked.
+                ///
+                /// # Safety
+                ///
+                /// As with [`MaybeUninit::assume_init`], the target must really be in
+                /// an initialized state.
+                pub unsafe fn assume_init(this: Self) -> $strongname<T> {
+                    let Self { ptr, owner } = this;
+                    let owner = owner.map(|owner| {
+                        if owner.wrapper_type_id() == TypeId::of::<Plain<MaybeUninit<T>>>()
+                            && core::ptr::addr_eq(ptr, owner.value_ptr())
+                        {
+                            // SAFETY: Plain<MaybeUninit<T>> has the same layout as Plain<T>,
+                            // and the caller promises that the value is now initialized.
+                            let raw = Owner::into_raw(owner).cast::<Plain<T>>();
+                            erase(unsafe { $basestrong::from_raw(raw.as_ptr().cast_const()) })
+                        } else {
+                            owner
+                        }
+                    });
+                    $strongname { ptr: ptr.cast(), owner }
+                }
+            }
+
+            impl<T: 'static $(+ $bound)*> $strongname<[MaybeUninit<T>]> {
+                /// Converts into a pointer to the initialized elements.
+                ///
+                #[doc = concat!("This is the slice equivalent of [`", stringify!($strongname), "::assume_init`].")]
+                ///
+                /// If the target is the entire slice of an allocation created by
+                #[doc = concat!("[`", stringify!($strongname), "::new_uninit_slice`], and `this` is the only strong or weak")]
+                /// pointer to that allocation, then the allocation becomes responsible
+                /// for dropping the elements once the last strong pointer is dropped.
+                /// Otherwise the allocation continues to treat the target as
+                /// uninitialized, and so the elements are never dropped.
+                ///
+                /// # Safety
+                ///
+                /// As with [`MaybeUninit::assume_init`], all of the elements must really
+                /// be in an initialized state.
+                pub unsafe fn assume_init_slice(this: Self) -> $strongname<[T]> {
+                    let Self { ptr, mut owner } = this;
+                    // Other pointers to the allocation still treat the elements as
+                    // uninitialized, and could overwrite them through safe code.
+                    if let Some(owner) = owner.as_mut().and_then(Owner::get_mut) {
+                        if owner.wrapper_type_id() == TypeId::of::<Inline>() {
+                            let inline = unsafe { &*(owner as *mut dyn Erased as *const Inline) };
+                            if inline.type_id == TypeId::of::<[T]>()
+                                && core::ptr::addr_eq(ptr, inline.value)
+                                && ptr.len() == inline.len
+                            {
+                                inline.init.store(true, core::sync::atomic::Ordering::Release);
+                            }
+                        }
+                    }
+                    $strongname {
+                        ptr: ptr as *const [T],
+                        owner,
+                    }
+                }
+            }
+
+            impl<T> $strongname<[T]> {
+                /// Creates new pointers to the first element and the rest of the current
+                /// pointer's target, within the same allocation.
+                ///
+                /// Returns `None` if the target slice is empty.
+                pub fn split_first(this: &Self) -> Option<($strongname<T>, Self)> {
+                    let r = unsafe { &*this.ptr };
+                    r.split_first().map(|(first, rest)| {
+                        (
+                            $strongname { ptr: first as *const _, owner: this.owner.clone() },
+                            $strongname { ptr: rest as *const _, owner: this.owner.clone() },
+                        )
+                    })
+                }
+
+                /// Creates new pointers to the last element and the rest of the current
+                /// pointer's target, within the same allocation.
+                ///
+                /// Returns `None` if the target slice is empty.
+                pub fn split_last(this: &Self) -> Option<($strongname<T>, Self)> {
+                    let r = unsafe { &*this.ptr };
+                    r.split_last().map(|(last, rest)| {
+                        (
+                            $strongname { ptr: last as *const _, owner: this.owner.clone() },
+                            $strongname { ptr: rest as *const _, owner: this.owner.clone() },
+                        )
+                    })
+                }
+
+                /// Returns an iterator over pointers to `chunk_size` elements of the
+                /// current pointer's target at a time, within the same allocation.
+                ///
+                /// This is the pointer-producing equivalent of [`slice::chunks`]. Use
+                /// [`Self::iter_map`] to iterate over pointers to the individual elements.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `chunk_size` is zero.
+                pub fn chunks(this: &Self, chunk_size: usize) -> IterMap<'_, core::slice::Chunks<'_, T>> {
+                    // Safety: Each chunk is a subslice of the target.
+                    unsafe { Self::iter_map_unchecked(this, |s| s.chunks(chunk_size)) }
+                }
+            }
+
+            /// Additional functions for pointers to string slices.
+            impl $strongname<str> {
+                /// Adopts the buffer of the given string as the target, without
+                /// copying its contents.
+                ///
+                #[doc = concat!("This behaves in the same way as [`", stringify!($strongname), "::from_vec`], but for strings.")]
+                pub fn from_string(v: alloc::string::String) -> Self {
+                    Self::from_indirect(v)
+                }
+
+                /// Returns an iterator over pointers to the substrings of the current
+                /// pointer's target separated by `pat`, within the same allocation.
+                ///
+                /// This is the pointer-producing equivalent of [`str::split`].
+                pub fn split<'a>(this: &'a Self, pat: &'a str) -> IterMap<'a, core::str::Split<'a, &'a str>> {
+                    // Safety: Each substring is a subslice of the target.
+                    unsafe { Self::iter_map_unchecked(this, |s| s.split(pat)) }
+                }
+
+                /// Returns an iterator over pointers to the lines of the current pointer's
+                /// target, within the same allocation.
+                ///
+                /// This is the pointer-producing equivalent of [`str::lines`].
+                pub fn lines(this: &Self) -> IterMap<'_, core::str::Lines<'_>> {
+                    // Safety: Each line is a subslice of the target.
+                    unsafe { Self::iter_map_unchecked(this, str::lines) }
+                }
+
+                /// Converts a pointer to a byte slice into a pointer to a string slice
+                /// within the same allocation, after checking that the bytes are valid UTF-8.
+                ///
+                /// If the bytes are not valid UTF-8 then returns the original pointer
+                /// along with the error.
+                pub fn from_utf8(v: $strongname<[u8]>) -> Result<Self, ($strongname<[u8]>, core::str::Utf8Error)> {
+                    $strongname::try_map(v, core::str::from_utf8)
+                }
             }
 
             impl<T: ?Sized> core::ops::Deref for $strongname<T> {
@@ -249,21 +1071,21 @@ over what it points at regardless of what it's contained within.
                 }
             }
 
-            impl<T> core::convert::AsRef<T> for $strongname<T> {
+            impl<T: ?Sized> core::convert::AsRef<T> for $strongname<T> {
                 #[inline(always)]
                 fn as_ref(&self) -> &T {
                     unsafe { &*self.ptr }
                 }
             }
 
-            impl<T> core::borrow::Borrow<T> for $strongname<T> {
+            impl<T: ?Sized> core::borrow::Borrow<T> for $strongname<T> {
                 #[inline(always)]
                 fn borrow(&self) -> &T {
                     unsafe { &*self.ptr }
                 }
             }
 
-            impl<T> core::clone::Clone for $strongname<T> {
+            impl<T: ?Sized> core::clone::Clone for $strongname<T> {
                 /// Creates a new pointer to the same value in the same allocation.
                 ///
                 /// This is equivalent to [`Self::clone`].
@@ -273,7 +1095,7 @@ over what it points at regardless of what it's contained within.
                 }
             }
 
-            impl<T: 'static> From<T> for $strongname<T> {
+            impl<T: 'static $(+ $bound)*> From<T> for $strongname<T> {
                 /// Moves the value into a heap allocation and returns the first strong reference to it.
                 ///
                 /// Equivalent to [`Self::new`].
@@ -283,7 +1105,7 @@ over what it points at regardless of what it's contained within.
                 }
             }
 
-            impl<T: 'static> From<$basestrong<T>> for $strongname<T> {
+            impl<T: 'static $(+ $bound)*> From<$basestrong<T>> for $strongname<T> {
                 /// Converts from the standard library implementation to this implementation while
                 /// reusing the same underlying allocation.
                 ///
@@ -294,28 +1116,165 @@ over what it points at regardless of what it's contained within.
                 }
             }
 
-            impl<T: 'static> From<alloc::boxed::Box<T>> for $strongname<T> {
+            impl<T: ?Sized + 'static $(+ $bound)*> From<alloc::boxed::Box<T>> for $strongname<T> {
                 /// Converts from the standard library implementation to this implementation while
                 /// reusing the same underlying allocation.
                 ///
-                /// Equivalent to [`Self::from_alloc`].
+                /// Equivalent to [`Self::from_box`].
+                #[inline(always)]
                 fn from(value: alloc::boxed::Box<T>) -> Self {
-                    let owner: $basestrong<T> = value.into();
-                    Self::from_alloc(owner)
+                    Self::from_box(value)
                 }
             }
 
-            impl<T: core::hash::Hash + ?Sized> core::hash::Hash for $strongname<T> {
-                #[inline]
-                fn hash<H>(&self, hasher: &mut H) where H: core::hash::Hasher {
-                    use core::ops::Deref;
-                    let r = self.deref();
-                    <T as core::hash::Hash>::hash(r, hasher)
+            impl From<$basestrong<str>> for $strongname<str> {
+                /// Converts from the standard library implementation to this implementation while
+                /// reusing the same underlying allocation.
+                ///
+                /// Equivalent to [`Self::from_unsized_alloc`].
+                #[inline(always)]
+                fn from(value: $basestrong<str>) -> Self {
+                    Self::from_unsized_alloc(value)
                 }
             }
 
-            impl<T: core::cmp::PartialEq + ?Sized> core::cmp::PartialEq for $strongname<T> {
-                #[inline]
+            impl<T: 'static $(+ $bound)*> From<$basestrong<[T]>> for $strongname<[T]> {
+                /// Converts from the standard library implementation to this implementation while
+                /// reusing the same underlying allocation.
+                ///
+                /// Equivalent to [`Self::from_unsized_alloc`].
+                #[inline(always)]
+                fn from(value: $basestrong<[T]>) -> Self {
+                    Self::from_unsized_alloc(value)
+                }
+            }
+
+            impl From<&str> for $strongname<str> {
+                /// Copies the string into a new allocation.
+                #[inline(always)]
+                fn from(value: &str) -> Self {
+                    Self::from_string(value.into())
+                }
+            }
+
+            impl<T: Clone + 'static $(+ $bound)*> From<&[T]> for $strongname<[T]> {
+                /// Clones the elements into a new allocation.
+                #[inline(always)]
+                fn from(value: &[T]) -> Self {
+                    Self::from_vec(value.to_vec())
+                }
+            }
+
+            impl<T> From<alloc::borrow::Cow<'static, T>> for $strongname<T>
+            where
+                T: ?Sized + alloc::borrow::ToOwned + 'static,
+                T::Owned: Into<$strongname<T>>,
+            {
+                /// Refers to a borrowed value using [`Self::from_static_ref`], without
+                /// allocating, or moves an owned value into a new allocation.
+                fn from(value: alloc::borrow::Cow<'static, T>) -> Self {
+                    match value {
+                        alloc::borrow::Cow::Borrowed(r) => Self::from_static_ref(r),
+                        alloc::borrow::Cow::Owned(v) => v.into(),
+                    }
+                }
+            }
+
+            impl<T: 'static $(+ $bound)*> FromIterator<T> for $strongname<[T]> {
+                /// Collects the items into a new allocation that also holds the reference
+                /// counts.
+                ///
+                /// If the iterator reports its exact length, as an [`ExactSizeIterator`]
+                /// does, then the items are written directly into the new allocation.
+                /// Otherwise, or if the iterator turns out to produce some other number
+                /// of items, they are first collected into a temporary vector.
+                fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+                    let mut iter = iter.into_iter();
+                    let mut items = match iter.size_hint() {
+                        (min, Some(max)) if min == max => match Self::try_from_iter_len(min, &mut iter) {
+                            Ok(this) => return this,
+                            Err(items) => items,
+                        },
+                        _ => alloc::vec::Vec::new(),
+                    };
+                    items.extend(iter);
+                    Self::from_exact_iter(items.len(), items.into_iter())
+                }
+            }
+
+            impl<T: 'static $(+ $bound)*> From<alloc::vec::Vec<T>> for $strongname<[T]> {
+                /// Adopts the vector's buffer without copying its elements.
+                ///
+                /// Equivalent to [`Self::from_vec`].
+                #[inline(always)]
+                fn from(value: alloc::vec::Vec<T>) -> Self {
+                    Self::from_vec(value)
+                }
+            }
+
+            impl From<alloc::string::String> for $strongname<str> {
+                /// Adopts the string's buffer without copying its contents.
+                ///
+                /// Equivalent to [`Self::from_string`].
+                #[inline(always)]
+                fn from(value: alloc::string::String) -> Self {
+                    Self::from_string(value)
+                }
+            }
+
+            impl<T, const N: usize> TryFrom<$strongname<[T]>> for $strongname<[T; N]> {
+                type Error = $strongname<[T]>;
+
+                /// Converts a pointer to a slice into a pointer to an array within the
+                /// same allocation, if the slice has exactly `N` elements.
+                ///
+                /// Returns the original pointer if the length does not match.
+                fn try_from(value: $strongname<[T]>) -> Result<Self, Self::Error> {
+                    $strongname::filter_map(value, |s| <&[T; N]>::try_from(s).ok())
+                }
+            }
+
+            impl<T: ?Sized> crate::Coerce for $strongname<T> {
+                type Target = T;
+                type Output<R: ?Sized> = $strongname<R>;
+
+                #[inline(always)]
+                fn coerce_with<R: ?Sized>(self, f: impl FnOnce(&T) -> &R) -> $strongname<R> {
+                    $strongname::map(self, f)
+                }
+            }
+
+            // DispatchFromDyn is not implemented because it requires all fields other
+            // than the pointer itself to be zero-sized, and the owner is not.
+            #[cfg(feature = "experimental_coerce_unsized")]
+            #[cfg_attr(docsrs, doc(cfg(feature = "experimental_coerce_unsized")))]
+            impl<T, U> core::ops::CoerceUnsized<$strongname<U>> for $strongname<T>
+            where
+                T: ?Sized + core::marker::Unsize<U>,
+                U: ?Sized,
+            {
+            }
+
+            #[cfg(feature = "experimental_coerce_unsized")]
+            #[cfg_attr(docsrs, doc(cfg(feature = "experimental_coerce_unsized")))]
+            impl<T, U> core::ops::CoerceUnsized<$uniquename<U>> for $uniquename<T>
+            where
+                T: ?Sized + core::marker::Unsize<U>,
+                U: ?Sized,
+            {
+            }
+
+            impl<T: core::hash::Hash + ?Sized> core::hash::Hash for $strongname<T> {
+                #[inline]
+                fn hash<H>(&self, hasher: &mut H) where H: core::hash::Hasher {
+                    use core::ops::Deref;
+                    let r = self.deref();
+                    <T as core::hash::Hash>::hash(r, hasher)
+                }
+            }
+
+            impl<T: core::cmp::PartialEq + ?Sized> core::cmp::PartialEq for $strongname<T> {
+                #[inline]
                 fn eq(&self, other: &Self) -> bool {
                     use core::ops::Deref;
                     let r1 = self.deref();
@@ -344,7 +1303,28 @@ over what it points at regardless of what it's contained within.
                 }
             }
 
-            impl<T: core::default::Default + 'static> core::default::Default for $strongname<T> {
+            impl core::cmp::PartialEq<str> for $strongname<str> {
+                #[inline]
+                fn eq(&self, other: &str) -> bool {
+                    **self == *other
+                }
+            }
+
+            impl core::cmp::PartialEq<&str> for $strongname<str> {
+                #[inline]
+                fn eq(&self, other: &&str) -> bool {
+                    **self == **other
+                }
+            }
+
+            impl<T: core::cmp::PartialEq> core::cmp::PartialEq<[T]> for $strongname<[T]> {
+                #[inline]
+                fn eq(&self, other: &[T]) -> bool {
+                    **self == *other
+                }
+            }
+
+            impl<T: core::default::Default + 'static $(+ $bound)*> core::default::Default for $strongname<T> {
                 #[inline(always)]
                 fn default() -> Self {
                     Self::new(T::default())
@@ -363,47 +1343,202 @@ over what it points at regardless of what it's contained within.
                 }
             }
 
-            impl<T: ?Sized + core::fmt::Pointer> core::fmt::Pointer for $strongname<T> {
+            impl<T: ?Sized> core::fmt::Pointer for $strongname<T> {
+                /// Formats the address of the target.
                 fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-                    core::fmt::Pointer::fmt(&**self, f)
+                    core::fmt::Pointer::fmt(&self.ptr, f)
                 }
             }
 
-            #[doc = concat!("Weak-reference counterpart of [`", stringify!($strongname), "`].")]
-            pub struct $weakname<T: ?Sized> {
-                ptr: *const T,
-                owner: Option<$baseweak<dyn Erased>>,
+            #[doc = concat!("Diagnostic view of an [`", stringify!($strongname), "`] and the allocation it refers to, as returned by [`", stringify!($strongname), "::debug_info`].")]
+            ///
+            /// The [`Debug`](core::fmt::Debug) output of this type is intended only for
+            /// humans and may change in future versions.
+            pub struct DebugInfo<'a, T: ?Sized>(&'a $strongname<T>);
+
+            impl<'a, T: ?Sized> core::fmt::Debug for DebugInfo<'a, T> {
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    let this = self.0;
+                    let mut s = f.debug_struct(stringify!($strongname));
+                    s.field("target", &format_args!("{:p}", this.ptr));
+                    match &this.owner {
+                        Some(owner) => {
+                            s.field("allocation_value", &format_args!("{:p}", owner.value_ptr()));
+                            s.field("allocation_size", &owner.value_size());
+                            s.field("allocation_type", &format_args!("{}", owner.value_type_name()));
+                            s.field("offset", &$strongname::offset_in_allocation(this));
+                            s.field("strong_count", &Owner::strong_count(owner));
+                            s.field("weak_count", &Owner::weak_count(owner));
+                            s.field("is_static", &false);
+                        }
+                        None => {
+                            s.field("is_static", &true);
+                        }
+                    }
+                    s.finish()
+                }
             }
 
-            impl<T: 'static> $weakname<T> {
-                /// Constructs a new weak reference without performing a dynamic allocation.
-                ///
-                /// Calling [`Self::upgrade`] on the result always returns `None`.
+            impl<T: ?Sized> core::marker::Unpin for $strongname<T> {}
+
+            impl<'a, T> IntoIterator for &'a $strongname<[T]> {
+                type Item = &'a T;
+                type IntoIter = core::slice::Iter<'a, T>;
+
                 #[inline(always)]
-                pub fn new() -> Self {
-                    let owner = $baseweak::new();
-                    Self {
-                        ptr: $baseweak::as_ptr(&owner),
-                        owner: Some(owner as $baseweak<dyn Erased>),
-                    }
+                fn into_iter(self) -> Self::IntoIter {
+                    self.iter()
+                }
+            }
+
+            #[doc = concat!("Wrapper around a [`", stringify!($strongname), "`] that compares, orders, and hashes by the address of the target rather than by its value.
+
+This allows using pointers as keys in maps and sets by their identity, even if
+the target type doesn't implement the relevant traits itself. As with
+[`", stringify!($strongname), "::ptr_eq`], only the target address is considered and so pointers to
+different objects at the same address are considered equal.")]
+            #[derive(Clone)]
+            pub struct ByAddress<P>(pub P);
+
+            impl<T: ?Sized> core::ops::Deref for ByAddress<$strongname<T>> {
+                type Target = $strongname<T>;
+
+                #[inline(always)]
+                fn deref(&self) -> &$strongname<T> {
+                    &self.0
+                }
+            }
+
+            impl<T: ?Sized> core::hash::Hash for ByAddress<$strongname<T>> {
+                #[inline]
+                fn hash<H>(&self, hasher: &mut H) where H: core::hash::Hasher {
+                    self.0.ptr.cast::<()>().hash(hasher)
+                }
+            }
+
+            impl<T: ?Sized> core::cmp::PartialEq for ByAddress<$strongname<T>> {
+                #[inline]
+                fn eq(&self, other: &Self) -> bool {
+                    $strongname::ptr_eq(&self.0, &other.0)
+                }
+            }
+
+            impl<T: ?Sized> core::cmp::Eq for ByAddress<$strongname<T>> {}
+
+            impl<T: ?Sized> core::cmp::PartialOrd for ByAddress<$strongname<T>> {
+                #[inline]
+                fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+                    Some(core::cmp::Ord::cmp(self, other))
+                }
+            }
+
+            impl<T: ?Sized> core::cmp::Ord for ByAddress<$strongname<T>> {
+                #[inline]
+                fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+                    self.0.ptr.cast::<()>().cmp(&other.0.ptr.cast::<()>())
+                }
+            }
+
+            impl<T: ?Sized + core::fmt::Debug> core::fmt::Debug for ByAddress<$strongname<T>> {
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    core::fmt::Debug::fmt(&self.0, f)
+                }
+            }
+
+            #[doc = concat!("Iterator over pointers into a shared allocation, created by [`", stringify!($strongname), "::iter_map`].")]
+            pub struct IterMap<'a, I> {
+                iter: I,
+                owner: &'a Option<Owner>,
+            }
+
+            impl<'a, I, R> Iterator for IterMap<'a, I>
+            where
+                I: Iterator<Item = &'a R>,
+                R: ?Sized + 'a,
+            {
+                type Item = $strongname<R>;
+
+                #[inline]
+                fn next(&mut self) -> Option<Self::Item> {
+                    self.iter.next().map(|r| $strongname {
+                        ptr: r as *const _,
+                        owner: self.owner.clone(),
+                    })
+                }
+
+                #[inline]
+                fn size_hint(&self) -> (usize, Option<usize>) {
+                    self.iter.size_hint()
+                }
+            }
+
+            impl<'a, I, R> DoubleEndedIterator for IterMap<'a, I>
+            where
+                I: DoubleEndedIterator<Item = &'a R>,
+                R: ?Sized + 'a,
+            {
+                #[inline]
+                fn next_back(&mut self) -> Option<Self::Item> {
+                    self.iter.next_back().map(|r| $strongname {
+                        ptr: r as *const _,
+                        owner: self.owner.clone(),
+                    })
+                }
+            }
+
+            impl<'a, I, R> ExactSizeIterator for IterMap<'a, I>
+            where
+                I: ExactSizeIterator<Item = &'a R>,
+                R: ?Sized + 'a,
+            {
+                #[inline]
+                fn len(&self) -> usize {
+                    self.iter.len()
                 }
+            }
 
+            impl<'a, I, R> core::iter::FusedIterator for IterMap<'a, I>
+            where
+                I: core::iter::FusedIterator<Item = &'a R>,
+                R: ?Sized + 'a,
+            {
+            }
+
+            #[doc = concat!("Weak-reference counterpart of [`", stringify!($strongname), "`].")]
+            pub struct $weakname<T: ?Sized> {
+                // This is `None` only for the result of `Weak::new`, which has no target.
+                ptr: Option<NonNull<T>>,
+                owner: Option<WeakOwner>,
+            }
+
+            impl<T: 'static $(+ $bound)*> $weakname<T> {
                 #[doc = concat!("Transforms an [`", stringify!($baseweak) ,"`] into an [`", stringify!($weakname), "`] referring to the same allocation.")]
                 #[inline(always)]
                 pub fn from_alloc(v: $baseweak<T>) -> Self {
                     Self {
-                        ptr: $baseweak::as_ptr(&v),
-                        owner: Some(v as $baseweak<dyn Erased>),
+                        ptr: NonNull::new($baseweak::as_ptr(&v).cast_mut()),
+                        owner: Some(erase_weak(plain_weak(v))),
                     }
                 }
             }
 
             impl<T: ?Sized> Weak<T> {
+                /// Constructs a new weak reference without performing a dynamic allocation.
+                ///
+                /// Calling [`Self::upgrade`] on the result always returns `None`.
+                #[inline(always)]
+                pub const fn new() -> Self {
+                    Self {
+                        ptr: None,
+                        owner: None,
+                    }
+                }
+
                 #[doc = concat!("Wraps the target of the given static reference in a [`", stringify!($weakname) ,"`] that does not actually perform any reference counting and performs no heap allocation, because a static object is never dropped.\n\nThis is here to allow both reference-counted and statically-allocated objects to be stored in the same type where that's convenient, but wastes two pointers of storage compared to just using the reference directly.")]
                 #[inline(always)]
                 pub const fn from_static_ref(r: &'static T) -> Self {
                     Self {
-                        ptr: r as *const _,
+                        ptr: NonNull::new(r as *const T as *mut T),
                         owner: None,
                     }
                 }
@@ -413,31 +1548,67 @@ over what it points at regardless of what it's contained within.
                 /// Returns `None` if there are no strong references left live.
                 #[inline(always)]
                 pub fn upgrade(&self) -> Option<$strongname<T>> {
+                    let ptr = self.ptr?.as_ptr().cast_const();
                     match &self.owner {
-                        Some(owner) => owner.upgrade().map(
+                        Some(owner) => owner.upgrade().filter(|owner| owner.is_frozen()).map(
                             #[inline(always)]
                             |owner| $strongname {
-                                ptr: self.ptr,
+                                ptr,
                                 owner: Some(owner),
                             },
                         ),
                         None => Some($strongname {
-                            ptr: self.ptr,
+                            ptr,
                             owner: None,
                         }),
                     }
                 }
 
+                /// Creates a new weak pointer to some part of the current pointer's target,
+                /// within the same allocation.
+                ///
+                #[doc = concat!("This is the weak counterpart of [`", stringify!($strongname), "::clone_map`]. The result shares the allocation of `self` without any new strong pointers being created, although the allocation is kept live while the closure is running.")]
+                ///
+                /// Returns `None` without calling the closure if the allocation is no
+                /// longer live, or if `self` was created using [`Self::new`].
+                pub fn clone_map<R: ?Sized>(&self, f: impl FnOnce(&T) -> &R) -> Option<$weakname<R>> {
+                    self.clone_filter_map(|v| Some(f(v)))
+                }
+
+                /// Conditionally creates a new weak pointer to some part of the current
+                /// pointer's target, within the same allocation.
+                ///
+                #[doc = concat!("This is the weak counterpart of [`", stringify!($strongname), "::clone_filter_map`], and behaves like [`Self::clone_map`] except that it also returns `None` if the closure does.")]
+                pub fn clone_filter_map<R: ?Sized>(
+                    &self,
+                    f: impl FnOnce(&T) -> Option<&R>,
+                ) -> Option<$weakname<R>> {
+                    let ptr = self.ptr?;
+                    // The closure could potentially drop the last strong pointer,
+                    // so we must hold one ourselves until it returns.
+                    let _live = match &self.owner {
+                        Some(owner) => Some(owner.upgrade().filter(|owner| owner.is_frozen())?),
+                        None => None,
+                    };
+                    let r = f(unsafe { ptr.as_ref() })?;
+                    Some($weakname {
+                        ptr: Some(NonNull::from(r)),
+                        owner: self.owner.clone(),
+                    })
+                }
+
                 /// Gets the number of strong pointers to this allocation.
                 ///
                 /// Returns [`usize::MAX`] if this reference was created using
                 /// [`Self::from_static_ref`], because there is no true
-                /// reference count for a static allocation.
+                /// reference count for a static allocation, and zero if it
+                /// was created using [`Self::new`].
                 #[inline(always)]
                 pub fn strong_count(&self) -> usize {
-                    match &self.owner {
-                        Some(owner) => owner.strong_count(),
-                        None => usize::MAX,
+                    match (&self.owner, self.ptr) {
+                        (Some(owner), _) => owner.strong_count(),
+                        (None, Some(_)) => usize::MAX,
+                        (None, None) => 0,
                     }
                 }
 
@@ -445,12 +1616,14 @@ over what it points at regardless of what it's contained within.
                 ///
                 /// Returns [`usize::MAX`] if this reference was created using
                 /// [`Self::from_static_ref`], because there is no true
-                /// reference count for a static allocation.
+                /// reference count for a static allocation, and zero if it
+                /// was created using [`Self::new`].
                 #[inline(always)]
                 pub fn weak_count(&self) -> usize {
-                    match &self.owner {
-                        Some(owner) => owner.weak_count(),
-                        None => usize::MAX,
+                    match (&self.owner, self.ptr) {
+                        (Some(owner), _) => owner.weak_count(),
+                        (None, Some(_)) => usize::MAX,
+                        (None, None) => 0,
                     }
                 }
 
@@ -462,18 +1635,62 @@ over what it points at regardless of what it's contained within.
                 /// reference for the full duration of the program.
                 #[inline(always)]
                 pub fn target_is_static(&self) -> bool {
-                    self.owner.is_none()
+                    self.owner.is_none() && self.ptr.is_some()
+                }
+
+                /// Returns `true` if the two weak pointers refer to the same address, or
+                /// if both were created using [`Self::new`].
+                ///
+                #[doc = concat!("This compares addresses in the same way as [`", stringify!($strongname), "::ptr_eq`].")]
+                #[inline(always)]
+                pub fn ptr_eq(&self, other: &Self) -> bool {
+                    match (self.ptr, other.ptr) {
+                        (Some(a), Some(b)) => core::ptr::addr_eq(a.as_ptr(), b.as_ptr()),
+                        (None, None) => true,
+                        _ => false,
+                    }
+                }
+
+                /// Returns `true` if the two weak pointers refer to targets within the
+                /// same reference-counted allocation, even if the targets themselves differ.
+                ///
+                /// Always returns `false` if either pointer was created using
+                /// [`Self::from_static_ref`] or [`Self::new`], because neither refers to
+                /// a reference-counted allocation.
+                #[inline(always)]
+                pub fn same_allocation<U: ?Sized>(&self, other: &$weakname<U>) -> bool {
+                    match (&self.owner, &other.owner) {
+                        (Some(a), Some(b)) => WeakOwner::ptr_eq(a, b),
+                        _ => false,
+                    }
+                }
+            }
+
+            impl<T: ?Sized> core::clone::Clone for $weakname<T> {
+                /// Creates a new weak pointer to the same value in the same allocation.
+                #[inline(always)]
+                fn clone(&self) -> Self {
+                    Self {
+                        ptr: self.ptr,
+                        owner: self.owner.clone(),
+                    }
+                }
+            }
+
+            impl<T: ?Sized> core::fmt::Debug for $weakname<T> {
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    f.write_str("(Weak)")
                 }
             }
 
-            impl<T: 'static> From<$baseweak<T>> for Weak<T> {
+            impl<T: 'static $(+ $bound)*> From<$baseweak<T>> for Weak<T> {
                 #[inline(always)]
                 fn from(value: $baseweak<T>) -> Self {
                     Self::from_alloc(value)
                 }
             }
 
-            impl<T: core::default::Default + 'static> core::default::Default for $weakname<T> {
+            impl<T: ?Sized> core::default::Default for $weakname<T> {
                 /// Returns a weak reference without any strong counterpart.
                 ///
                 /// Equivalent to [`Self::new`].
@@ -482,20 +1699,1883 @@ over what it points at regardless of what it's contained within.
                     Self::new()
                 }
             }
-        }
-    };
-}
 
-#[cfg(feature = "rc")]
-rc_wrapper!(rc, Rc, Weak, AllocRc, AllocRcWeak);
-#[cfg(feature = "arc")]
-rc_wrapper!(arc, Arc, Weak, AllocArc, AllocArcWeak);
+            #[doc = concat!("Uniquely-owned pointer to all or part of a new reference-counted heap allocation, which can later be converted into an [`", stringify!($strongname), "`].
 
-/// An object-safe trait with no methods and thus whose trait objects
-/// contain only an implementer's size and drop glue. The only requirement
-/// is that the implementer not contain any non-static references, because
-/// the smart pointer types would not be able to keep track of those
-/// references.
-#[allow(unused)]
-trait Erased {}
-impl<T: 'static> Erased for T {}
+This is intended for building a value in-place before sharing it. While a
+`", stringify!($uniquename), "` exists it is the only pointer that can access the allocation, and so
+it allows mutable access to its target and can be narrowed to mutable parts of
+that target using [`", stringify!($uniquename), "::map_mut`].
+
+[`", stringify!($uniquename), "::downgrade`] creates [`", stringify!($weakname), "`] pointers to the allocation that
+cannot be upgraded until the unique pointer is converted using
+[`", stringify!($uniquename), "::", stringify!($intoname), "`]. This is similar to [`", stringify!($strongname), "::new_cyclic`], but
+without needing to construct the entire value inside a closure. If the unique
+pointer is dropped without being converted then the allocation is freed and
+the weak pointers can never be upgraded.
+")]
+            pub struct $uniquename<T: ?Sized> {
+                ptr: *mut T,
+                owner: Owner,
+            }
+
+            impl<T: 'static $(+ $bound)*> $uniquename<T> {
+                /// Creates a new reference-counted allocation containing the given value,
+                /// initially owned only by the result.
+                #[inline(always)]
+                pub fn new(v: T) -> Self {
+                    let alloc = $basestrong::new(Unique::new(v));
+                    let ptr = alloc.value.get();
+                    Self { ptr, owner: erase(alloc) }
+                }
+            }
+
+            impl<T: ?Sized> $uniquename<T> {
+                /// Creates a new unique pointer to some part of the current pointer's
+                /// target, within the same allocation, consuming the current pointer.
+                ///
+                /// The closure receives a mutable reference to the pointer's target and
+                /// must return a mutable reference with the same lifetime. The remainder
+                /// of the target is then inaccessible until the allocation is shared.
+                pub fn map_mut<R: ?Sized>(this: Self, f: impl FnOnce(&mut T) -> &mut R) -> $uniquename<R> {
+                    let r = f(unsafe { &mut *this.ptr });
+                    $uniquename {
+                        ptr: r as *mut _,
+                        owner: this.owner,
+                    }
+                }
+
+                /// Creates a weak pointer to the same target value.
+                ///
+                /// The result cannot be upgraded until `this` is converted using
+                #[doc = concat!("[`Self::", stringify!($intoname), "`]. Until then, its strong count is always one.")]
+                pub fn downgrade(this: &Self) -> $weakname<T> {
+                    $weakname {
+                        ptr: NonNull::new(this.ptr),
+                        owner: Some(Owner::downgrade(&this.owner)),
+                    }
+                }
+
+                #[doc = concat!("Converts the unique pointer into a shared [`", stringify!($strongname), "`] referring to the same target, without reallocating.
+
+After this, the weak pointers created using [`Self::downgrade`] can be upgraded.")]
+                pub fn $intoname(this: Self) -> $strongname<T> {
+                    this.owner.freeze();
+                    $strongname {
+                        ptr: this.ptr as *const _,
+                        owner: Some(this.owner),
+                    }
+                }
+            }
+
+            impl<T: ?Sized> core::ops::Deref for $uniquename<T> {
+                type Target = T;
+
+                /// Returns a reference to the pointee.
+                #[inline(always)]
+                fn deref(&self) -> &T {
+                    unsafe { &*self.ptr }
+                }
+            }
+
+            impl<T: ?Sized> core::ops::DerefMut for $uniquename<T> {
+                /// Returns a mutable reference to the pointee.
+                #[inline(always)]
+                fn deref_mut(&mut self) -> &mut T {
+                    unsafe { &mut *self.ptr }
+                }
+            }
+
+            impl<T: ?Sized> From<$uniquename<T>> for $strongname<T> {
+                /// Converts the unique pointer into a shared pointer without reallocating.
+                ///
+                #[doc = concat!("Equivalent to [`", stringify!($uniquename), "::", stringify!($intoname), "`].")]
+                #[inline(always)]
+                fn from(value: $uniquename<T>) -> Self {
+                    $uniquename::$intoname(value)
+                }
+            }
+
+            impl<T: ?Sized + core::fmt::Debug> core::fmt::Debug for $uniquename<T> {
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    core::fmt::Debug::fmt(&**self, f)
+                }
+            }
+
+            #[doc = concat!("Variant of [`", stringify!($strongname), "`] whose allocation may borrow data that lives only for `'a`.
+
+[`", stringify!($strongname), "`] erases the type of its allocation, which requires the allocation's value
+to be `'static`. This type instead erases only enough to drop the allocation, and
+so can own a value that borrows from a buffer or arena that lives only for a
+limited scope. In return, it supports only the part of the API that doesn't
+depend on knowing the type of the allocation at runtime, such as
+[`", stringify!($scopedname), "::clone_map`] and [`", stringify!($scopedname), "::downgrade`].
+
+Any [`", stringify!($strongname), "`] can be converted into a `", stringify!($scopedname), "` without reallocating.
+")]
+            pub struct $scopedname<'a, T: ?Sized> {
+                ptr: *const T,
+                owner: Option<Owner<dyn Counted + 'a>>,
+            }
+
+            impl<'a, T: 'a $(+ $bound)*> $scopedname<'a, T> {
+                /// Creates a new reference-counted allocation containing the given value.
+                #[inline(always)]
+                pub fn new(v: T) -> Self {
+                    Self::from_alloc($basestrong::new(v))
+                }
+
+                #[doc = concat!("Transforms an [`", stringify!($basestrong) ,"`] into a [`", stringify!($scopedname), "`] referring to the same allocation.")]
+                #[inline(always)]
+                pub fn from_alloc(v: $basestrong<T>) -> Self {
+                    let ptr = $basestrong::as_ptr(&v);
+                    Self { ptr, owner: Some(erase_scoped(plain_alloc(v))) }
+                }
+            }
+
+            impl<'a, T: ?Sized> $scopedname<'a, T> {
+                /// Wraps the target of the given reference without performing any
+                /// reference counting or heap allocation.
+                ///
+                #[doc = concat!("This is the equivalent of [`", stringify!($strongname), "::from_static_ref`], but the reference need only live for `'a`.")]
+                #[inline(always)]
+                pub const fn from_ref(r: &'a T) -> Self {
+                    Self {
+                        ptr: r as *const _,
+                        owner: None,
+                    }
+                }
+
+                /// Builds an object (unsafely) from its raw parts.
+                ///
+                /// # Safety
+                ///
+                /// `ptr` must point to something inside `owner`'s heap
+                /// allocation, which would therefore remain valid as long as
+                /// that allocation is live.
+                #[inline(always)]
+                pub unsafe fn from_raw_parts<AllocT: 'a $(+ $bound)*>(ptr: *const T, owner: $basestrong<AllocT>) -> Self {
+                    Self { ptr, owner: Some(erase_scoped(plain_alloc(owner))) }
+                }
+
+                /// Gets a raw pointer to the target.
+                ///
+                /// The counts are not affected in any way and the pointer remains valid
+                /// for as long as at least one strong reference remains live.
+                #[inline(always)]
+                pub const fn as_ptr(this: &Self) -> *const T {
+                    this.ptr
+                }
+
+                /// Creates a new pointer to the same object.
+                ///
+                /// This increments the reference count for the underlying allocation.
+                #[inline(always)]
+                #[allow(clippy::should_implement_trait)]
+                pub fn clone(this: &Self) -> Self {
+                    Self {
+                        ptr: this.ptr,
+                        owner: this.owner.clone(),
+                    }
+                }
+
+                /// Creates a new pointer to some part of the current pointer's target,
+                /// within the same allocation.
+                ///
+                #[doc = concat!("This behaves in the same way as [`", stringify!($strongname), "::clone_map`].")]
+                pub fn clone_map<R: ?Sized>(this: &Self, f: impl FnOnce(&T) -> &R) -> $scopedname<'a, R> {
+                    let r = f(unsafe { &*this.ptr });
+                    $scopedname {
+                        ptr: r as *const _,
+                        owner: this.owner.clone(),
+                    }
+                }
+
+                /// Conditionally creates a new pointer to some part of the current pointer's
+                /// target, within the same allocation.
+                ///
+                #[doc = concat!("This behaves in the same way as [`", stringify!($strongname), "::clone_filter_map`].")]
+                pub fn clone_filter_map<R: ?Sized>(
+                    this: &Self,
+                    f: impl FnOnce(&T) -> Option<&R>,
+                ) -> Option<$scopedname<'a, R>> {
+                    f(unsafe { &*this.ptr }).map(|r| $scopedname {
+                        ptr: r as *const _,
+                        owner: this.owner.clone(),
+                    })
+                }
+
+                /// Creates a new pointer to some part of the current pointer's target,
+                /// within the same allocation, consuming the current pointer.
+                ///
+                #[doc = concat!("This behaves in the same way as [`", stringify!($strongname), "::map`].")]
+                pub fn map<R: ?Sized>(this: Self, f: impl FnOnce(&T) -> &R) -> $scopedname<'a, R> {
+                    let r = f(unsafe { &*this.ptr });
+                    $scopedname {
+                        ptr: r as *const _,
+                        owner: this.owner,
+                    }
+                }
+
+                /// Conditionally creates a new pointer to some part of the current pointer's
+                /// target, within the same allocation, consuming the current pointer.
+                ///
+                #[doc = concat!("This behaves in the same way as [`", stringify!($strongname), "::filter_map`].")]
+                pub fn filter_map<R: ?Sized>(
+                    this: Self,
+                    f: impl FnOnce(&T) -> Option<&R>,
+                ) -> Result<$scopedname<'a, R>, Self> {
+                    match f(unsafe { &*this.ptr }) {
+                        Some(r) => Ok($scopedname {
+                            ptr: r as *const _,
+                            owner: this.owner,
+                        }),
+                        None => Err(this),
+                    }
+                }
+
+                /// Creates a weak pointer to the same target value.
+                pub fn downgrade(this: &Self) -> ScopedWeak<'a, T> {
+                    ScopedWeak {
+                        ptr: this.ptr,
+                        owner: this.owner.as_ref().map(Owner::downgrade),
+                    }
+                }
+
+                /// Returns `true` if the two pointers have the same target address.
+                #[inline(always)]
+                pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+                    core::ptr::addr_eq(this.ptr, other.ptr)
+                }
+
+                /// Returns `true` if the two pointers refer to targets within the same
+                /// reference-counted allocation, even if the targets themselves differ.
+                ///
+                /// Always returns `false` if either pointer was created using
+                /// [`Self::from_ref`].
+                #[inline(always)]
+                pub fn same_allocation<U: ?Sized>(this: &Self, other: &$scopedname<'a, U>) -> bool {
+                    match (&this.owner, &other.owner) {
+                        (Some(a), Some(b)) => Owner::ptr_eq(a, b),
+                        _ => false,
+                    }
+                }
+
+                /// Gets the number of strong pointers to this allocation.
+                ///
+                /// Returns [`usize::MAX`] if this reference was created using
+                /// [`Self::from_ref`], because there is no true reference count
+                /// for a borrowed object.
+                #[inline(always)]
+                pub fn strong_count(this: &Self) -> usize {
+                    match &this.owner {
+                        Some(owner) => Owner::strong_count(owner),
+                        None => usize::MAX,
+                    }
+                }
+
+                /// Gets the number of weak pointers to this allocation.
+                ///
+                /// Returns [`usize::MAX`] if this reference was created using
+                /// [`Self::from_ref`], because there is no true reference count
+                /// for a borrowed object.
+                #[inline(always)]
+                pub fn weak_count(this: &Self) -> usize {
+                    match &this.owner {
+                        Some(owner) => Owner::weak_count(owner),
+                        None => usize::MAX,
+                    }
+                }
+
+                /// Returns `true` if `this` refers to a borrowed object rather
+                /// than to a reference-counted object.
+                #[inline(always)]
+                pub fn target_is_borrowed(this: &Self) -> bool {
+                    this.owner.is_none()
+                }
+            }
+
+            impl<'a, T: ?Sized> core::ops::Deref for $scopedname<'a, T> {
+                type Target = T;
+
+                /// Returns a reference to the pointee.
+                #[inline(always)]
+                fn deref(&self) -> &T {
+                    unsafe { &*self.ptr }
+                }
+            }
+
+            impl<'a, T: ?Sized> core::clone::Clone for $scopedname<'a, T> {
+                /// Creates a new pointer to the same value in the same allocation.
+                ///
+                /// This is equivalent to [`Self::clone`].
+                #[inline(always)]
+                fn clone(&self) -> Self {
+                    $scopedname::<T>::clone(self)
+                }
+            }
+
+            impl<'a, T: ?Sized> From<$strongname<T>> for $scopedname<'a, T> {
+                /// Converts into a scoped pointer to the same target, without reallocating.
+                #[inline(always)]
+                fn from(value: $strongname<T>) -> Self {
+                    Self {
+                        ptr: value.ptr,
+                        owner: value.owner.map(Owner::upcast),
+                    }
+                }
+            }
+
+            impl<'a, T: ?Sized + core::fmt::Debug> core::fmt::Debug for $scopedname<'a, T> {
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    core::fmt::Debug::fmt(&**self, f)
+                }
+            }
+
+            impl<'a, T: ?Sized + core::fmt::Display> core::fmt::Display for $scopedname<'a, T> {
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    core::fmt::Display::fmt(&**self, f)
+                }
+            }
+
+            impl<'a, T: ?Sized> core::fmt::Pointer for $scopedname<'a, T> {
+                /// Formats the address of the target.
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    core::fmt::Pointer::fmt(&self.ptr, f)
+                }
+            }
+
+            #[doc = concat!("Weak-reference counterpart of [`", stringify!($scopedname), "`].")]
+            pub struct ScopedWeak<'a, T: ?Sized> {
+                ptr: *const T,
+                owner: Option<WeakOwner<dyn Counted + 'a>>,
+            }
+
+            impl<'a, T: ?Sized> ScopedWeak<'a, T> {
+                #[doc = concat!("Attempts to upgrade to a [`", stringify!($scopedname), "`], returning `None` if the allocation has already been dropped.")]
+                pub fn upgrade(&self) -> Option<$scopedname<'a, T>> {
+                    let owner = match &self.owner {
+                        Some(owner) => Some(owner.upgrade()?),
+                        None => None,
+                    };
+                    Some($scopedname { ptr: self.ptr, owner })
+                }
+
+                /// Gets the number of strong pointers to this allocation.
+                ///
+                #[doc = concat!("Returns [`usize::MAX`] if this reference was created from a borrowed object using [`", stringify!($scopedname), "::from_ref`].")]
+                #[inline(always)]
+                pub fn strong_count(&self) -> usize {
+                    match &self.owner {
+                        Some(owner) => owner.strong_count(),
+                        None => usize::MAX,
+                    }
+                }
+            }
+
+            impl<'a, T: ?Sized> core::clone::Clone for ScopedWeak<'a, T> {
+                #[inline(always)]
+                fn clone(&self) -> Self {
+                    Self {
+                        ptr: self.ptr,
+                        owner: self.owner.clone(),
+                    }
+                }
+            }
+
+            impl<'a, T: ?Sized> core::fmt::Debug for ScopedWeak<'a, T> {
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    write!(f, "(Weak)")
+                }
+            }
+
+            #[doc = concat!("Variant of [`", stringify!($strongname), "`] that retains the type `A` of the allocation it refers to.
+
+This stores the allocation as an [`", stringify!($basestrong), "<A>`](", stringify!($basestrong), ") rather than as a type-erased
+owner, and so for a sized `T` it is only two pointers in size and dropping it
+does not need to make any dynamic calls. It also allows direct access to the
+whole allocation using [`", stringify!($typedname), "::owner`].
+
+A `", stringify!($typedname), "` can be converted into an [`", stringify!($strongname), "`] without reallocating,
+such as when passing it to code that doesn't know the allocation type.
+")]
+            pub struct $typedname<A, T: ?Sized> {
+                ptr: *const T,
+                owner: $basestrong<A>,
+            }
+
+            impl<A> $typedname<A, A> {
+                /// Creates a new reference-counted allocation containing the given value.
+                #[inline(always)]
+                pub fn new(v: A) -> Self {
+                    Self::from_alloc($basestrong::new(v))
+                }
+
+                #[doc = concat!("Transforms an [`", stringify!($basestrong) ,"`] into a [`", stringify!($typedname), "`] referring to the same allocation.")]
+                #[inline(always)]
+                pub fn from_alloc(v: $basestrong<A>) -> Self {
+                    let ptr = $basestrong::as_ptr(&v);
+                    Self { ptr, owner: v }
+                }
+            }
+
+            impl<A, T: ?Sized> $typedname<A, T> {
+                /// Builds an object (unsafely) from its raw parts.
+                ///
+                /// # Safety
+                ///
+                /// `ptr` must point to something inside `owner`'s heap
+                /// allocation, which would therefore remain valid as long as
+                /// that allocation is live.
+                #[inline(always)]
+                pub unsafe fn from_raw_parts(ptr: *const T, owner: $basestrong<A>) -> Self {
+                    Self { ptr, owner }
+                }
+
+                /// Returns a reference to the entire value of the allocation that
+                /// contains the target.
+                #[inline(always)]
+                pub fn owner(this: &Self) -> &A {
+                    &this.owner
+                }
+
+                #[doc = concat!("Returns the [`", stringify!($basestrong), "`] that owns the allocation containing the target.")]
+                #[inline(always)]
+                pub fn owner_alloc(this: &Self) -> &$basestrong<A> {
+                    &this.owner
+                }
+
+                /// Gets a raw pointer to the target.
+                ///
+                /// The counts are not affected in any way and the pointer remains valid
+                /// for as long as at least one strong reference remains live.
+                #[inline(always)]
+                pub const fn as_ptr(this: &Self) -> *const T {
+                    this.ptr
+                }
+
+                /// Creates a new pointer to the same object.
+                ///
+                /// This increments the reference count for the underlying allocation.
+                #[inline(always)]
+                #[allow(clippy::should_implement_trait)]
+                pub fn clone(this: &Self) -> Self {
+                    Self {
+                        ptr: this.ptr,
+                        owner: this.owner.clone(),
+                    }
+                }
+
+                /// Creates a new pointer to some part of the current pointer's target,
+                /// within the same allocation.
+                ///
+                #[doc = concat!("This behaves in the same way as [`", stringify!($strongname), "::clone_map`].")]
+                pub fn clone_map<R: ?Sized>(this: &Self, f: impl FnOnce(&T) -> &R) -> $typedname<A, R> {
+                    let r = f(unsafe { &*this.ptr });
+                    $typedname {
+                        ptr: r as *const _,
+                        owner: this.owner.clone(),
+                    }
+                }
+
+                /// Conditionally creates a new pointer to some part of the current pointer's
+                /// target, within the same allocation.
+                ///
+                #[doc = concat!("This behaves in the same way as [`", stringify!($strongname), "::clone_filter_map`].")]
+                pub fn clone_filter_map<R: ?Sized>(
+                    this: &Self,
+                    f: impl FnOnce(&T) -> Option<&R>,
+                ) -> Option<$typedname<A, R>> {
+                    f(unsafe { &*this.ptr }).map(|r| $typedname {
+                        ptr: r as *const _,
+                        owner: this.owner.clone(),
+                    })
+                }
+
+                /// Creates a new pointer to some part of the current pointer's target,
+                /// within the same allocation, consuming the current pointer.
+                ///
+                #[doc = concat!("This behaves in the same way as [`", stringify!($strongname), "::map`].")]
+                pub fn map<R: ?Sized>(this: Self, f: impl FnOnce(&T) -> &R) -> $typedname<A, R> {
+                    let r = f(unsafe { &*this.ptr });
+                    $typedname {
+                        ptr: r as *const _,
+                        owner: this.owner,
+                    }
+                }
+
+                /// Conditionally creates a new pointer to some part of the current pointer's
+                /// target, within the same allocation, consuming the current pointer.
+                ///
+                #[doc = concat!("This behaves in the same way as [`", stringify!($strongname), "::filter_map`].")]
+                pub fn filter_map<R: ?Sized>(
+                    this: Self,
+                    f: impl FnOnce(&T) -> Option<&R>,
+                ) -> Result<$typedname<A, R>, Self> {
+                    match f(unsafe { &*this.ptr }) {
+                        Some(r) => Ok($typedname {
+                            ptr: r as *const _,
+                            owner: this.owner,
+                        }),
+                        None => Err(this),
+                    }
+                }
+
+                /// Fallibly creates a new pointer to some part of the current pointer's
+                /// target, within the same allocation, consuming the current pointer.
+                ///
+                #[doc = concat!("This behaves in the same way as [`", stringify!($strongname), "::try_map`].")]
+                pub fn try_map<R: ?Sized, E>(
+                    this: Self,
+                    f: impl FnOnce(&T) -> Result<&R, E>,
+                ) -> Result<$typedname<A, R>, (Self, E)> {
+                    match f(unsafe { &*this.ptr }) {
+                        Ok(r) => Ok($typedname {
+                            ptr: r as *const _,
+                            owner: this.owner,
+                        }),
+                        Err(e) => Err((this, e)),
+                    }
+                }
+
+                /// Returns `true` if the two pointers have the same target address.
+                #[inline(always)]
+                pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+                    core::ptr::addr_eq(this.ptr, other.ptr)
+                }
+
+                /// Returns `true` if the two pointers refer to targets within the same
+                /// reference-counted allocation, even if the targets themselves differ.
+                #[inline(always)]
+                pub fn same_allocation<U: ?Sized>(this: &Self, other: &$typedname<A, U>) -> bool {
+                    $basestrong::ptr_eq(&this.owner, &other.owner)
+                }
+
+                /// Gets the number of strong pointers to this allocation.
+                #[inline(always)]
+                pub fn strong_count(this: &Self) -> usize {
+                    $basestrong::strong_count(&this.owner)
+                }
+
+                /// Gets the number of weak pointers to this allocation.
+                #[inline(always)]
+                pub fn weak_count(this: &Self) -> usize {
+                    $basestrong::weak_count(&this.owner)
+                }
+
+                #[doc = concat!("Converts into an [`", stringify!($strongname), "`] to the same target, without reallocating.")]
+                ///
+                /// The result no longer tracks the type of the allocation.
+                #[inline(always)]
+                pub fn erase(this: Self) -> $strongname<T>
+                where
+                    A: 'static $(+ $bound)*,
+                {
+                    $strongname {
+                        ptr: this.ptr,
+                        owner: Some(erase(plain_alloc(this.owner))),
+                    }
+                }
+            }
+
+            impl<A, T: ?Sized> core::ops::Deref for $typedname<A, T> {
+                type Target = T;
+
+                /// Returns a reference to the pointee.
+                #[inline(always)]
+                fn deref(&self) -> &T {
+                    unsafe { &*self.ptr }
+                }
+            }
+
+            impl<A, T: ?Sized> core::clone::Clone for $typedname<A, T> {
+                /// Creates a new pointer to the same value in the same allocation.
+                ///
+                /// This is equivalent to [`Self::clone`].
+                #[inline(always)]
+                fn clone(&self) -> Self {
+                    $typedname::<A, T>::clone(self)
+                }
+            }
+
+            impl<A: 'static $(+ $bound)*, T: ?Sized> From<$typedname<A, T>> for $strongname<T> {
+                /// Converts into a pointer to the same target, without reallocating.
+                ///
+                #[doc = concat!("Equivalent to [`", stringify!($typedname), "::erase`].")]
+                #[inline(always)]
+                fn from(value: $typedname<A, T>) -> Self {
+                    $typedname::erase(value)
+                }
+            }
+
+            impl<A, T: ?Sized + core::fmt::Debug> core::fmt::Debug for $typedname<A, T> {
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    core::fmt::Debug::fmt(&**self, f)
+                }
+            }
+
+            impl<A, T: ?Sized + core::fmt::Display> core::fmt::Display for $typedname<A, T> {
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    core::fmt::Display::fmt(&**self, f)
+                }
+            }
+
+            impl<A, T: ?Sized> core::fmt::Pointer for $typedname<A, T> {
+                /// Formats the address of the target.
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    core::fmt::Pointer::fmt(&self.ptr, f)
+                }
+            }
+
+            /// Type-erased owner of a strong reference to a reference-counted allocation.
+            ///
+            #[doc = concat!("This is the equivalent of an [`", stringify!($basestrong), "<E>`](", stringify!($basestrong), "), but it performs the reference counting")]
+            /// using the operations returned by [`Counted::count_ops`] and so it can also
+            /// own an [`Inline`] allocation, whose counts are stored alongside a
+            /// dynamically-sized value. The strong and weak counts and the uniqueness
+            /// check therefore have the same meaning for both kinds of allocation.
+            struct Owner<E: ?Sized + Counted = dyn Erased> {
+                ptr: NonNull<E>,
+            }
+
+            // SAFETY: The owner is equivalent to the standard library pointer, and
+            // Inline allocations are Send and Sync only with atomic reference counts.
+            unsafe impl<E: ?Sized + Counted> Send for Owner<E> where $basestrong<E>: Send {}
+            unsafe impl<E: ?Sized + Counted> Sync for Owner<E> where $basestrong<E>: Sync {}
+
+            impl<E: ?Sized + Counted> Owner<E> {
+                #[inline(always)]
+                fn ops(this: &Self) -> &'static CountOps {
+                    this.count_ops()
+                }
+
+                #[inline(always)]
+                fn raw(this: &Self) -> *const () {
+                    this.ptr.as_ptr().cast_const().cast()
+                }
+
+                #[inline(always)]
+                fn as_ptr(this: &Self) -> *const E {
+                    this.ptr.as_ptr()
+                }
+
+                #[inline(always)]
+                fn strong_count(this: &Self) -> usize {
+                    unsafe { (Self::ops(this).strong_count)(Self::raw(this)) }
+                }
+
+                #[inline(always)]
+                fn weak_count(this: &Self) -> usize {
+                    unsafe { (Self::ops(this).weak_count)(Self::raw(this)) }
+                }
+
+                /// Returns a mutable reference to the value if there are no other strong
+                /// or weak references to the allocation.
+                fn get_mut(this: &mut Self) -> Option<&mut E> {
+                    if unsafe { (Self::ops(this).is_unique)(Self::raw(this)) } {
+                        Some(unsafe { this.ptr.as_mut() })
+                    } else {
+                        None
+                    }
+                }
+
+                fn downgrade(this: &Self) -> WeakOwner<E> {
+                    let ops = Self::ops(this);
+                    unsafe { (ops.downgrade)(Self::raw(this)) };
+                    WeakOwner { ptr: this.ptr, ops }
+                }
+
+                #[inline(always)]
+                fn ptr_eq(this: &Self, other: &Self) -> bool {
+                    core::ptr::addr_eq(this.ptr.as_ptr(), other.ptr.as_ptr())
+                }
+
+                /// Consumes the owner without releasing its strong reference.
+                #[inline(always)]
+                fn into_raw(this: Self) -> NonNull<E> {
+                    let ptr = this.ptr;
+                    core::mem::forget(this);
+                    ptr
+                }
+
+                /// Takes over a strong reference released by [`Self::into_raw`].
+                ///
+                /// # Safety
+                ///
+                /// `ptr` must have been returned by [`Self::into_raw`], possibly with
+                /// different metadata that describes the same kind of allocation.
+                #[inline(always)]
+                unsafe fn from_raw(ptr: NonNull<E>) -> Self {
+                    Self { ptr }
+                }
+            }
+
+            impl Owner {
+                /// Converts into an owner that only allows dropping the allocation.
+                #[inline(always)]
+                fn upcast<'a>(this: Self) -> Owner<dyn Counted + 'a> {
+                    Owner { ptr: Self::into_raw(this) }
+                }
+            }
+
+            impl<E: ?Sized + Counted> core::ops::Deref for Owner<E> {
+                type Target = E;
+
+                #[inline(always)]
+                fn deref(&self) -> &E {
+                    unsafe { self.ptr.as_ref() }
+                }
+            }
+
+            impl<E: ?Sized + Counted> core::clone::Clone for Owner<E> {
+                #[inline(always)]
+                fn clone(&self) -> Self {
+                    unsafe { (Self::ops(self).clone)(Self::raw(self)) };
+                    Self { ptr: self.ptr }
+                }
+            }
+
+            impl<E: ?Sized + Counted> Drop for Owner<E> {
+                #[inline(always)]
+                fn drop(&mut self) {
+                    unsafe { (Self::ops(self).drop)(Self::raw(self)) }
+                }
+            }
+
+            /// Type-erased owner of a weak reference to a reference-counted allocation.
+            ///
+            /// The value might already have been dropped, or might not have been
+            /// initialized yet, so this keeps its own reference to the counting
+            /// operations rather than calling [`Counted::count_ops`] on the value.
+            struct WeakOwner<E: ?Sized + Counted = dyn Erased> {
+                ptr: NonNull<E>,
+                ops: &'static CountOps,
+            }
+
+            // SAFETY: As for Owner.
+            unsafe impl<E: ?Sized + Counted> Send for WeakOwner<E> where $baseweak<E>: Send {}
+            unsafe impl<E: ?Sized + Counted> Sync for WeakOwner<E> where $baseweak<E>: Sync {}
+
+            impl<E: ?Sized + Counted> WeakOwner<E> {
+                #[inline(always)]
+                fn raw(&self) -> *const () {
+                    self.ptr.as_ptr().cast_const().cast()
+                }
+
+                fn upgrade(&self) -> Option<Owner<E>> {
+                    if unsafe { (self.ops.upgrade)(self.raw()) } {
+                        Some(Owner { ptr: self.ptr })
+                    } else {
+                        None
+                    }
+                }
+
+                #[inline(always)]
+                fn strong_count(&self) -> usize {
+                    unsafe { (self.ops.weak_strong_count)(self.raw()) }
+                }
+
+                #[inline(always)]
+                fn weak_count(&self) -> usize {
+                    unsafe { (self.ops.weak_weak_count)(self.raw()) }
+                }
+
+                #[inline(always)]
+                fn ptr_eq(this: &Self, other: &Self) -> bool {
+                    core::ptr::addr_eq(this.ptr.as_ptr(), other.ptr.as_ptr())
+                }
+            }
+
+            impl<E: ?Sized + Counted> core::clone::Clone for WeakOwner<E> {
+                #[inline(always)]
+                fn clone(&self) -> Self {
+                    unsafe { (self.ops.clone_weak)(self.raw()) };
+                    Self { ptr: self.ptr, ops: self.ops }
+                }
+            }
+
+            impl<E: ?Sized + Counted> Drop for WeakOwner<E> {
+                #[inline(always)]
+                fn drop(&mut self) {
+                    unsafe { (self.ops.drop_weak)(self.raw()) }
+                }
+            }
+
+            /// The minimal interface of the value of a reference-counted allocation,
+            /// which allows only counting references to it and eventually dropping it.
+            #[doc = concat!("This is what [`", stringify!($scopedname), "`] uses to own an allocation whose value is")]
+            /// not `'static`, and is a supertrait of [`Erased`] so that any owner can be
+            /// converted into this one.
+            trait Counted where Self: $($bound +)* {
+                /// Returns the operations for counting references to the allocation
+                /// whose value is `self`.
+                fn count_ops(&self) -> &'static CountOps;
+            }
+
+            /// The counting operations for a standard library allocation of type `W`.
+            struct StdOps<W>(core::marker::PhantomData<W>);
+
+            impl<W> StdOps<W> {
+                const OPS: CountOps = CountOps {
+                    clone: |p| unsafe { $basestrong::increment_strong_count(p.cast::<W>()) },
+                    drop: |p| unsafe { $basestrong::decrement_strong_count(p.cast::<W>()) },
+                    strong_count: |p| $basestrong::strong_count(&*unsafe { Self::strong(p) }),
+                    weak_count: |p| $basestrong::weak_count(&*unsafe { Self::strong(p) }),
+                    is_unique: |p| $basestrong::get_mut(&mut *unsafe { Self::strong(p) }).is_some(),
+                    downgrade: |p| core::mem::forget($basestrong::downgrade(&*unsafe { Self::strong(p) })),
+                    clone_weak: |p| core::mem::forget($baseweak::clone(&*unsafe { Self::weak(p) })),
+                    drop_weak: |p| drop(unsafe { $baseweak::from_raw(p.cast::<W>()) }),
+                    upgrade: |p| unsafe { Self::weak(p) }.upgrade().map(core::mem::forget).is_some(),
+                    weak_strong_count: |p| unsafe { Self::weak(p) }.strong_count(),
+                    weak_weak_count: |p| unsafe { Self::weak(p) }.weak_count(),
+                };
+
+                /// Borrows the strong reference that `p` represents.
+                ///
+                /// # Safety
+                ///
+                #[doc = concat!("`p` must have been returned by [`", stringify!($basestrong), "::into_raw`] for a strong reference that is still live.")]
+                #[inline(always)]
+                unsafe fn strong(p: *const ()) -> core::mem::ManuallyDrop<$basestrong<W>> {
+                    core::mem::ManuallyDrop::new(unsafe { $basestrong::from_raw(p.cast()) })
+                }
+
+                /// Borrows the weak reference that `p` represents.
+                ///
+                /// # Safety
+                ///
+                #[doc = concat!("`p` must have been returned by [`", stringify!($baseweak), "::into_raw`] for a weak reference that is still live.")]
+                #[inline(always)]
+                unsafe fn weak(p: *const ()) -> core::mem::ManuallyDrop<$baseweak<W>> {
+                    core::mem::ManuallyDrop::new(unsafe { $baseweak::from_raw(p.cast()) })
+                }
+            }
+
+            impl<T> Counted for Plain<T> where Self: $($bound +)* {
+                #[inline(always)]
+                fn count_ops(&self) -> &'static CountOps {
+                    &StdOps::<Self>::OPS
+                }
+            }
+
+            impl<T> Counted for Cloneable<T> where Self: $($bound +)* {
+                #[inline(always)]
+                fn count_ops(&self) -> &'static CountOps {
+                    &StdOps::<Self>::OPS
+                }
+            }
+
+            impl<T> Counted for Unique<T> where Self: $($bound +)* {
+                #[inline(always)]
+                fn count_ops(&self) -> &'static CountOps {
+                    &StdOps::<Self>::OPS
+                }
+            }
+
+            impl<P> Counted for Indirect<P> where Self: $($bound +)* {
+                #[inline(always)]
+                fn count_ops(&self) -> &'static CountOps {
+                    &StdOps::<Self>::OPS
+                }
+            }
+
+            impl Counted for Inline {
+                #[inline(always)]
+                fn count_ops(&self) -> &'static CountOps {
+                    &Inline::OPS
+                }
+            }
+
+            /// An object-safe trait implemented by the types used as the contents of a
+            /// reference-counted allocation, so that the smart pointer types can own an
+            /// allocation without knowing its type.
+            ///
+            /// The implementers are wrappers around the allocated value, and the choice
+            /// of wrapper decides which additional capabilities are available for the
+            /// allocation. The only requirement for the wrapped value is that it not
+            /// contain any non-static references, because the smart pointer types
+            /// would not be able to keep track of those references.
+            trait Erased: Counted + 'static $(+ $bound)* {
+                /// Creates a new allocation containing a clone of this one, or returns
+                /// `None` if the allocation's type is not known to be cloneable.
+                fn clone_alloc(&self) -> Option<Owner>;
+
+                /// Returns the type of the wrapped value.
+                fn value_type_id(&self) -> TypeId;
+
+                /// Returns the name of the type of the wrapped value.
+                fn value_type_name(&self) -> &'static str;
+
+                /// Returns the type of the wrapper itself.
+                #[inline(always)]
+                fn wrapper_type_id(&self) -> TypeId {
+                    TypeId::of::<Self>()
+                }
+
+                /// Returns a pointer to the wrapped value.
+                fn value_ptr(&self) -> *const u8;
+
+                /// Returns a mutable pointer to the wrapped value, or `None` if it is
+                /// shared with something other than the allocation.
+                fn value_mut(&mut self) -> Option<*mut u8>;
+
+                /// Returns the size of the wrapped value.
+                fn value_size(&self) -> usize;
+
+                /// Returns `true` if the wrapper has the same layout as the value it
+                /// wraps, and so the allocation can be treated as an allocation of the
+                /// value's type.
+                #[inline(always)]
+                fn is_transparent(&self) -> bool {
+                    true
+                }
+
+                /// Returns `false` if the allocation is still owned by a unique pointer
+                /// and so no other pointer may access it yet.
+                #[inline(always)]
+                fn is_frozen(&self) -> bool {
+                    true
+                }
+
+                /// Marks the allocation as no longer owned by a unique pointer, so
+                /// that weak pointers to it can be upgraded.
+                #[inline(always)]
+                fn freeze(&self) {}
+            }
+
+            impl<T: 'static $(+ $bound)*> Erased for Plain<T> {
+                #[inline(always)]
+                fn clone_alloc(&self) -> Option<Owner> {
+                    None
+                }
+
+                #[inline(always)]
+                fn value_type_id(&self) -> TypeId {
+                    TypeId::of::<T>()
+                }
+
+                #[inline(always)]
+                fn value_ptr(&self) -> *const u8 {
+                    (&self.0 as *const T).cast()
+                }
+
+                #[inline(always)]
+                fn value_mut(&mut self) -> Option<*mut u8> {
+                    Some((&mut self.0 as *mut T).cast())
+                }
+
+                #[inline(always)]
+                fn value_size(&self) -> usize {
+                    core::mem::size_of::<T>()
+                }
+
+                #[inline(always)]
+                fn value_type_name(&self) -> &'static str {
+                    core::any::type_name::<T>()
+                }
+            }
+
+            impl<T: Clone + 'static $(+ $bound)*> Erased for Cloneable<T> {
+                fn clone_alloc(&self) -> Option<Owner> {
+                    Some(erase($basestrong::new(Cloneable(self.0.clone()))))
+                }
+
+                #[inline(always)]
+                fn value_type_id(&self) -> TypeId {
+                    TypeId::of::<T>()
+                }
+
+                #[inline(always)]
+                fn value_ptr(&self) -> *const u8 {
+                    (&self.0 as *const T).cast()
+                }
+
+                #[inline(always)]
+                fn value_mut(&mut self) -> Option<*mut u8> {
+                    Some((&mut self.0 as *mut T).cast())
+                }
+
+                #[inline(always)]
+                fn value_size(&self) -> usize {
+                    core::mem::size_of::<T>()
+                }
+
+                #[inline(always)]
+                fn value_type_name(&self) -> &'static str {
+                    core::any::type_name::<T>()
+                }
+            }
+
+            impl<T: 'static $(+ $bound)*> Erased for Unique<T> {
+                #[inline(always)]
+                fn clone_alloc(&self) -> Option<Owner> {
+                    None
+                }
+
+                #[inline(always)]
+                fn value_type_id(&self) -> TypeId {
+                    TypeId::of::<T>()
+                }
+
+                #[inline(always)]
+                fn value_ptr(&self) -> *const u8 {
+                    self.value.get().cast_const().cast()
+                }
+
+                #[inline(always)]
+                fn value_mut(&mut self) -> Option<*mut u8> {
+                    Some((self.value.get_mut() as *mut T).cast())
+                }
+
+                #[inline(always)]
+                fn value_size(&self) -> usize {
+                    core::mem::size_of::<T>()
+                }
+
+                #[inline(always)]
+                fn value_type_name(&self) -> &'static str {
+                    core::any::type_name::<T>()
+                }
+
+                #[inline(always)]
+                fn is_transparent(&self) -> bool {
+                    false
+                }
+
+                #[inline(always)]
+                fn is_frozen(&self) -> bool {
+                    self.frozen.load(core::sync::atomic::Ordering::Acquire)
+                }
+
+                #[inline(always)]
+                fn freeze(&self) {
+                    self.frozen.store(true, core::sync::atomic::Ordering::Release)
+                }
+            }
+
+            impl<P> Erased for Indirect<P>
+            where
+                P: IndirectOwner + 'static $(+ $bound)*,
+                P::Target: 'static,
+            {
+                #[inline(always)]
+                fn clone_alloc(&self) -> Option<Owner> {
+                    None
+                }
+
+                #[inline(always)]
+                fn value_type_id(&self) -> TypeId {
+                    TypeId::of::<P::Target>()
+                }
+
+                #[inline(always)]
+                fn value_ptr(&self) -> *const u8 {
+                    (&*self.0 as *const P::Target).cast()
+                }
+
+                #[inline(always)]
+                fn value_mut(&mut self) -> Option<*mut u8> {
+                    self.0.get_mut().map(|v| v.cast())
+                }
+
+                #[inline(always)]
+                fn value_size(&self) -> usize {
+                    core::mem::size_of_val(&*self.0)
+                }
+
+                #[inline(always)]
+                fn value_type_name(&self) -> &'static str {
+                    core::any::type_name::<P::Target>()
+                }
+
+                #[inline(always)]
+                fn is_transparent(&self) -> bool {
+                    false
+                }
+            }
+
+            impl Erased for Inline {
+                #[inline(always)]
+                fn clone_alloc(&self) -> Option<Owner> {
+                    None
+                }
+
+                #[inline(always)]
+                fn value_type_id(&self) -> TypeId {
+                    self.type_id
+                }
+
+                #[inline(always)]
+                fn value_ptr(&self) -> *const u8 {
+                    self.value.cast_const()
+                }
+
+                #[inline(always)]
+                fn value_mut(&mut self) -> Option<*mut u8> {
+                    Some(self.value)
+                }
+
+                #[inline(always)]
+                fn value_size(&self) -> usize {
+                    self.value_size
+                }
+
+                #[inline(always)]
+                fn value_type_name(&self) -> &'static str {
+                    self.type_name
+                }
+
+                #[inline(always)]
+                fn is_transparent(&self) -> bool {
+                    false
+                }
+            }
+
+            /// Takes over the strong reference of a standard library pointer as a
+            /// type-erased owner.
+            #[inline(always)]
+            fn erase<W: Erased>(v: $basestrong<W>) -> Owner {
+                // SAFETY: The standard library pointers are never null.
+                let ptr = unsafe { NonNull::new_unchecked($basestrong::into_raw(v).cast_mut()) };
+                Owner { ptr }
+            }
+
+            #[doc = concat!("Takes over the strong reference of a standard library pointer as an owner for [`", stringify!($scopedname), "`].")]
+            #[inline(always)]
+            fn erase_scoped<'a, W: Counted + 'a>(v: $basestrong<W>) -> Owner<dyn Counted + 'a> {
+                // SAFETY: The standard library pointers are never null.
+                let ptr = unsafe { NonNull::new_unchecked($basestrong::into_raw(v).cast_mut()) };
+                Owner { ptr }
+            }
+
+            /// Takes over the weak reference of a standard library pointer as a
+            /// type-erased owner.
+            #[inline(always)]
+            fn erase_weak<W: Erased>(v: $baseweak<W>) -> WeakOwner {
+                // SAFETY: The standard library pointers are never null, even for a
+                // weak pointer that has no allocation.
+                let ptr = unsafe { NonNull::new_unchecked($baseweak::into_raw(v).cast_mut()) };
+                WeakOwner { ptr, ops: &StdOps::<W>::OPS }
+            }
+
+            /// Reinterprets an allocation of `T` as an allocation of [`Plain<T>`],
+            /// which has the same layout.
+            #[inline(always)]
+            fn plain_alloc<T>(v: $basestrong<T>) -> $basestrong<Plain<T>> {
+                unsafe { $basestrong::from_raw($basestrong::into_raw(v).cast()) }
+            }
+
+            /// Reinterprets a weak pointer to an allocation of `T` as a weak pointer
+            /// to an allocation of [`Plain<T>`], which has the same layout.
+            #[inline(always)]
+            fn plain_weak<T>(v: $baseweak<T>) -> $baseweak<Plain<T>> {
+                unsafe { $baseweak::from_raw($baseweak::into_raw(v).cast()) }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "rc")]
+rc_wrapper!(
+    rc,
+    Rc,
+    Weak,
+    UniqueRc,
+    ScopedRc,
+    TypedRc,
+    into_rc,
+    AllocRc,
+    AllocRcWeak,
+    core::cell::Cell<usize>,
+    []
+);
+#[cfg(feature = "arc")]
+rc_wrapper!(
+    arc,
+    Arc,
+    Weak,
+    UniqueArc,
+    ScopedArc,
+    TypedArc,
+    into_arc,
+    AllocArc,
+    AllocArcWeak,
+    core::sync::atomic::AtomicUsize,
+    [Send, Sync]
+);
+
+/// Consumes a smart pointer and returns a pointer to the same target coerced
+/// to a different type, such as a trait object or a slice.
+///
+/// This works with both [`rc::Rc`](crate::rc::Rc) and [`arc::Arc`](crate::arc::Arc),
+/// and is equivalent to calling their `map` function with a closure that
+/// coerces the reference it is given. The result type must be known from
+/// the context, as with any other unsizing coercion.
+///
+/// With the `experimental_coerce_unsized` feature enabled these coercions
+/// can happen implicitly instead, as with the standard library pointer types,
+/// but that feature requires a nightly build of Rust.
+#[macro_export]
+macro_rules! coerce {
+    ($ptr:expr) => {
+        $crate::Coerce::coerce_with($ptr, |v| v as _)
+    };
+}
+
+/// Implementation detail of [`coerce!`], implemented by each of the strong
+/// pointer types.
+#[doc(hidden)]
+pub trait Coerce {
+    type Target: ?Sized;
+    type Output<R: ?Sized>;
+
+    fn coerce_with<R: ?Sized>(self, f: impl FnOnce(&Self::Target) -> &R) -> Self::Output<R>;
+}
+
+/// A header value followed by a dynamically-sized value, stored together in a
+/// single allocation.
+///
+/// This is the target type of the `from_header_and_iter` constructors, whose
+/// result can then be projected into separate pointers to the header and to
+/// the slice using `clone_map`.
+#[derive(Debug)]
+#[repr(C)]
+pub struct HeaderSlice<H, S: ?Sized> {
+    pub header: H,
+    pub slice: S,
+}
+
+/// The reference-counting operations for one kind of type-erased owner.
+///
+/// Each operation takes a pointer to the value of the allocation, as stored
+/// by the owner. The strong operations may assume that the caller holds a
+/// strong reference, and the weak operations that it holds a weak reference.
+#[allow(unused)]
+struct CountOps {
+    clone: unsafe fn(*const ()),
+    drop: unsafe fn(*const ()),
+    strong_count: unsafe fn(*const ()) -> usize,
+    weak_count: unsafe fn(*const ()) -> usize,
+    /// Returns `true` if there are no other strong or weak references.
+    is_unique: unsafe fn(*const ()) -> bool,
+    /// Adds a weak reference, which the caller then owns.
+    downgrade: unsafe fn(*const ()),
+    clone_weak: unsafe fn(*const ()),
+    drop_weak: unsafe fn(*const ()),
+    /// Adds a strong reference if there are any left, returning whether it did.
+    upgrade: unsafe fn(*const ()) -> bool,
+    weak_strong_count: unsafe fn(*const ()) -> usize,
+    weak_weak_count: unsafe fn(*const ()) -> usize,
+}
+
+/// The limit on reference counts, beyond which they are assumed to have been
+/// leaked intentionally and so we panic rather than risk an overflow.
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+/// A reference count for an [`Inline`] allocation, with the subset of the
+/// atomic integer API that the allocation uses.
+///
+/// This is implemented by [`AtomicUsize`](core::sync::atomic::AtomicUsize) for
+/// allocations that can be shared between threads, and by [`Cell<usize>`](core::cell::Cell)
+/// for those that can't, which then ignores the memory orderings.
+#[allow(unused)]
+trait Count {
+    fn new(v: usize) -> Self;
+    fn load(&self, order: core::sync::atomic::Ordering) -> usize;
+    fn store(&self, v: usize, order: core::sync::atomic::Ordering);
+    fn fetch_add(&self, v: usize, order: core::sync::atomic::Ordering) -> usize;
+    fn fetch_sub(&self, v: usize, order: core::sync::atomic::Ordering) -> usize;
+    fn compare_exchange(
+        &self,
+        current: usize,
+        new: usize,
+        success: core::sync::atomic::Ordering,
+        failure: core::sync::atomic::Ordering,
+    ) -> Result<usize, usize>;
+    fn compare_exchange_weak(
+        &self,
+        current: usize,
+        new: usize,
+        success: core::sync::atomic::Ordering,
+        failure: core::sync::atomic::Ordering,
+    ) -> Result<usize, usize>;
+}
+
+impl Count for core::sync::atomic::AtomicUsize {
+    #[inline(always)]
+    fn new(v: usize) -> Self {
+        Self::new(v)
+    }
+
+    #[inline(always)]
+    fn load(&self, order: core::sync::atomic::Ordering) -> usize {
+        Self::load(self, order)
+    }
+
+    #[inline(always)]
+    fn store(&self, v: usize, order: core::sync::atomic::Ordering) {
+        Self::store(self, v, order)
+    }
+
+    #[inline(always)]
+    fn fetch_add(&self, v: usize, order: core::sync::atomic::Ordering) -> usize {
+        Self::fetch_add(self, v, order)
+    }
+
+    #[inline(always)]
+    fn fetch_sub(&self, v: usize, order: core::sync::atomic::Ordering) -> usize {
+        Self::fetch_sub(self, v, order)
+    }
+
+    #[inline(always)]
+    fn compare_exchange(
+        &self,
+        current: usize,
+        new: usize,
+        success: core::sync::atomic::Ordering,
+        failure: core::sync::atomic::Ordering,
+    ) -> Result<usize, usize> {
+        Self::compare_exchange(self, current, new, success, failure)
+    }
+
+    #[inline(always)]
+    fn compare_exchange_weak(
+        &self,
+        current: usize,
+        new: usize,
+        success: core::sync::atomic::Ordering,
+        failure: core::sync::atomic::Ordering,
+    ) -> Result<usize, usize> {
+        Self::compare_exchange_weak(self, current, new, success, failure)
+    }
+}
+
+impl Count for core::cell::Cell<usize> {
+    #[inline(always)]
+    fn new(v: usize) -> Self {
+        Self::new(v)
+    }
+
+    #[inline(always)]
+    fn load(&self, _: core::sync::atomic::Ordering) -> usize {
+        self.get()
+    }
+
+    #[inline(always)]
+    fn store(&self, v: usize, _: core::sync::atomic::Ordering) {
+        self.set(v)
+    }
+
+    #[inline(always)]
+    fn fetch_add(&self, v: usize, _: core::sync::atomic::Ordering) -> usize {
+        self.replace(self.get().wrapping_add(v))
+    }
+
+    #[inline(always)]
+    fn fetch_sub(&self, v: usize, _: core::sync::atomic::Ordering) -> usize {
+        self.replace(self.get().wrapping_sub(v))
+    }
+
+    #[inline(always)]
+    fn compare_exchange(
+        &self,
+        current: usize,
+        new: usize,
+        _: core::sync::atomic::Ordering,
+        _: core::sync::atomic::Ordering,
+    ) -> Result<usize, usize> {
+        let old = self.get();
+        if old == current {
+            self.set(new);
+            Ok(old)
+        } else {
+            Err(old)
+        }
+    }
+
+    #[inline(always)]
+    fn compare_exchange_weak(
+        &self,
+        current: usize,
+        new: usize,
+        success: core::sync::atomic::Ordering,
+        failure: core::sync::atomic::Ordering,
+    ) -> Result<usize, usize> {
+        Count::compare_exchange(self, current, new, success, failure)
+    }
+}
+
+/// Header of a reference-counted allocation made by this crate rather than by
+/// the standard library, which stores the reference counts and the value in
+/// the same allocation even when the value is dynamically-sized.
+///
+/// The value is a `HeaderSlice<H, [T]>` that follows this header, and so a
+/// type-erased owner that points to the header can find everything it needs
+/// without knowing `H` or `T`. The counts behave in the same way as those of
+/// [`alloc::sync::Arc`], including the extra weak reference that is shared by
+/// all of the strong references, but they are atomic only if `C` is.
+#[allow(unused)]
+struct Inline<C> {
+    strong: C,
+    /// Set to `usize::MAX` while checking whether the allocation is unique.
+    weak: C,
+    /// `false` until the value has been initialized, and so must be dropped.
+    init: core::sync::atomic::AtomicBool,
+    /// Points to the value, with the provenance of the entire allocation.
+    value: *mut u8,
+    len: usize,
+    value_size: usize,
+    layout: core::alloc::Layout,
+    type_id: core::any::TypeId,
+    type_name: &'static str,
+    drop_value: unsafe fn(*mut u8, usize),
+    /// Frees the allocation, given a pointer to its start and its layout.
+    dealloc: unsafe fn(*mut u8, core::alloc::Layout),
+}
+
+// SAFETY: With atomic counts the header itself is only mutated through atomics.
+// Those allocations are only used by `Arc`, whose constructors require the value
+// to be Send and Sync.
+unsafe impl Send for Inline<core::sync::atomic::AtomicUsize> {}
+unsafe impl Sync for Inline<core::sync::atomic::AtomicUsize> {}
+
+#[allow(unused)]
+impl<C: Count> Inline<C> {
+    const OPS: CountOps = CountOps {
+        clone: |p| {
+            let old = unsafe { Self::header(p) }
+                .strong
+                .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+            assert!(old <= MAX_REFCOUNT, "reference count overflow");
+        },
+        drop: |p| {
+            use core::sync::atomic::{fence, Ordering};
+            {
+                let header = unsafe { Self::header(p) };
+                if header.strong.fetch_sub(1, Ordering::Release) != 1 {
+                    return;
+                }
+                fence(Ordering::Acquire);
+                if header.init.load(Ordering::Relaxed) {
+                    unsafe { (header.drop_value)(header.value, header.len) };
+                }
+            }
+            // Release the weak reference shared by all of the strong references.
+            unsafe { Self::drop_weak(p) };
+        },
+        strong_count: |p| {
+            unsafe { Self::header(p) }
+                .strong
+                .load(core::sync::atomic::Ordering::Acquire)
+        },
+        weak_count: |p| match unsafe { Self::header(p) }
+            .weak
+            .load(core::sync::atomic::Ordering::Acquire)
+        {
+            usize::MAX => 0,
+            n => n - 1,
+        },
+        is_unique: |p| {
+            use core::sync::atomic::Ordering;
+            let header = unsafe { Self::header(p) };
+            // Locking the weak count prevents a new weak reference being
+            // created from a strong reference that we haven't counted yet.
+            if header
+                .weak
+                .compare_exchange(1, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                return false;
+            }
+            let unique = header.strong.load(Ordering::Acquire) == 1;
+            header.weak.store(1, Ordering::Release);
+            unique
+        },
+        downgrade: |p| {
+            use core::sync::atomic::Ordering;
+            let header = unsafe { Self::header(p) };
+            let mut cur = header.weak.load(Ordering::Relaxed);
+            loop {
+                if cur == usize::MAX {
+                    core::hint::spin_loop();
+                    cur = header.weak.load(Ordering::Relaxed);
+                    continue;
+                }
+                assert!(cur <= MAX_REFCOUNT, "reference count overflow");
+                match header.weak.compare_exchange_weak(
+                    cur,
+                    cur + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return,
+                    Err(old) => cur = old,
+                }
+            }
+        },
+        clone_weak: |p| {
+            let old = unsafe { Self::header(p) }
+                .weak
+                .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+            assert!(old <= MAX_REFCOUNT, "reference count overflow");
+        },
+        drop_weak: Self::drop_weak,
+        upgrade: |p| {
+            use core::sync::atomic::Ordering;
+            let header = unsafe { Self::header(p) };
+            let mut cur = header.strong.load(Ordering::Relaxed);
+            loop {
+                if cur == 0 {
+                    return false;
+                }
+                assert!(cur <= MAX_REFCOUNT, "reference count overflow");
+                match header.strong.compare_exchange_weak(
+                    cur,
+                    cur + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return true,
+                    Err(old) => cur = old,
+                }
+            }
+        },
+        weak_strong_count: |p| {
+            unsafe { Self::header(p) }
+                .strong
+                .load(core::sync::atomic::Ordering::Acquire)
+        },
+        weak_weak_count: |p| {
+            use core::sync::atomic::Ordering;
+            let header = unsafe { Self::header(p) };
+            let weak = header.weak.load(Ordering::Acquire);
+            if header.strong.load(Ordering::Acquire) == 0 {
+                0
+            } else {
+                weak - 1
+            }
+        },
+    };
+
+    /// Releases a weak reference, freeing the allocation if it was the last.
+    ///
+    /// # Safety
+    ///
+    /// `p` must point to the header of a live allocation, and the caller must
+    /// own one of its weak references.
+    unsafe fn drop_weak(p: *const ()) {
+        use core::sync::atomic::{fence, Ordering};
+        let header = unsafe { Self::header(p) };
+        if header.weak.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        fence(Ordering::Acquire);
+        let (dealloc, layout) = (header.dealloc, header.layout);
+        unsafe { dealloc(p.cast_mut().cast(), layout) };
+    }
+
+    /// Returns the header that `p` points to.
+    ///
+    /// # Safety
+    ///
+    /// `p` must point to the header of a live allocation.
+    #[inline(always)]
+    unsafe fn header<'a>(p: *const ()) -> &'a Self {
+        unsafe { &*p.cast::<Self>() }
+    }
+}
+
+/// Pointers to the header and to the value of a new [`Inline`] allocation.
+#[allow(unused)]
+type InlineParts<C, H, T> = (core::ptr::NonNull<Inline<C>>, *mut HeaderSlice<H, [T]>);
+
+/// Allocates an [`Inline`] allocation containing a `HeaderSlice<H, [T]>` whose
+/// slice has `len` elements taken from `iter`, with a single strong reference.
+///
+/// Returns pointers to the header and to the value. The allocation reports `V`
+/// as the type of its value, and drops the value using `drop_value` once it
+/// has been marked as initialized, which happens immediately if `init` is set.
+///
+/// An iterator is allowed to report the wrong length, so if `iter` produces
+/// fewer or more than `len` items then this instead returns the header and
+/// the items it has taken from `iter` so far, leaving the rest in `iter`.
+#[allow(unused)]
+fn new_inline<C: Count, V: ?Sized + 'static, H, T>(
+    header: H,
+    len: usize,
+    iter: &mut impl Iterator<Item = T>,
+    drop_value: unsafe fn(*mut u8, usize),
+    init: bool,
+) -> Result<InlineParts<C, H, T>, (H, alloc::vec::Vec<T>)> {
+    use core::alloc::Layout;
+
+    /// Cleans up a partially-initialized allocation if the iterator panics.
+    struct Guard<H, T> {
+        mem: *mut u8,
+        layout: Layout,
+        value: *mut u8,
+        elems: *mut T,
+        written: usize,
+        _phantom: core::marker::PhantomData<(H, T)>,
+    }
+
+    impl<H, T> Drop for Guard<H, T> {
+        fn drop(&mut self) {
+            unsafe {
+                core::ptr::drop_in_place(self.value.cast::<H>());
+                core::ptr::drop_in_place(core::ptr::slice_from_raw_parts_mut(
+                    self.elems,
+                    self.written,
+                ));
+                alloc::alloc::dealloc(self.mem, self.layout);
+            }
+        }
+    }
+
+    let (value_layout, elems_offset) = Layout::new::<H>()
+        .extend(Layout::array::<T>(len).expect("slice too large"))
+        .expect("slice too large");
+    // This matches the `repr(C)` layout of `HeaderSlice<H, [T]>`.
+    let value_layout = value_layout.pad_to_align();
+    let (layout, value_offset) = Layout::new::<Inline<C>>()
+        .extend(value_layout)
+        .expect("slice too large");
+    let layout = layout.pad_to_align();
+    // The layout is never zero-sized, because it includes the header.
+    let mem = unsafe { alloc::alloc::alloc(layout) };
+    if mem.is_null() {
+        alloc::alloc::handle_alloc_error(layout);
+    }
+    let value = mem.wrapping_add(value_offset);
+
+    let mut guard = Guard::<H, T> {
+        mem,
+        layout,
+        value,
+        elems: value.wrapping_add(elems_offset).cast(),
+        written: 0,
+        _phantom: core::marker::PhantomData,
+    };
+    unsafe { value.cast::<H>().write(header) };
+    while guard.written < len {
+        let Some(item) = iter.next() else { break };
+        unsafe { guard.elems.add(guard.written).write(item) };
+        guard.written += 1;
+    }
+    let extra = if guard.written == len {
+        iter.next()
+    } else {
+        None
+    };
+    if guard.written < len || extra.is_some() {
+        // Reserve first so that nothing can panic once the items are moved out.
+        let mut items = alloc::vec::Vec::with_capacity(guard.written + 1);
+        let header = unsafe {
+            items.extend((0..guard.written).map(|i| guard.elems.add(i).read()));
+            value.cast::<H>().read()
+        };
+        items.extend(extra);
+        unsafe { alloc::alloc::dealloc(mem, layout) };
+        core::mem::forget(guard);
+        return Err((header, items));
+    }
+    core::mem::forget(guard);
+
+    unsafe {
+        mem.cast::<Inline<C>>().write(Inline {
+            strong: C::new(1),
+            weak: C::new(1),
+            init: core::sync::atomic::AtomicBool::new(init),
+            value,
+            len,
+            value_size: value_layout.size(),
+            layout,
+            type_id: core::any::TypeId::of::<V>(),
+            type_name: core::any::type_name::<V>(),
+            drop_value,
+            dealloc: alloc::alloc::dealloc,
+        })
+    };
+    // The slice length carries over as the metadata of the value pointer.
+    let ptr =
+        core::ptr::slice_from_raw_parts_mut(value.cast::<T>(), len) as *mut HeaderSlice<H, [T]>;
+    Ok((
+        unsafe { core::ptr::NonNull::new_unchecked(mem.cast()) },
+        ptr,
+    ))
+}
+
+/// Returns the layout of an [`Inline`] allocation of a `T` in the allocator
+/// `A`, which stores the allocator between the header and the value, along
+/// with the offsets of the allocator and of the value.
+#[cfg(feature = "experimental_allocator_api")]
+fn inline_in_layout<C, A, T>() -> (core::alloc::Layout, usize, usize) {
+    use core::alloc::Layout;
+
+    let (layout, alloc_offset) = Layout::new::<Inline<C>>()
+        .extend(Layout::new::<A>())
+        .expect("allocation too large");
+    let (layout, value_offset) = layout
+        .extend(Layout::new::<T>())
+        .expect("allocation too large");
+    (layout.pad_to_align(), alloc_offset, value_offset)
+}
+
+/// Allocates an [`Inline`] allocation containing `v` in the allocator `alloc`,
+/// which is also where the reference counts are stored, with a single strong
+/// reference.
+///
+/// Returns pointers to the header and to the value.
+#[cfg(feature = "experimental_allocator_api")]
+fn new_inline_in<C: Count, T: 'static, A: alloc::alloc::Allocator>(
+    v: T,
+    alloc: A,
+) -> Result<(core::ptr::NonNull<Inline<C>>, *mut T), alloc::alloc::AllocError> {
+    let (layout, alloc_offset, value_offset) = inline_in_layout::<C, A, T>();
+    let mem = alloc.allocate(layout)?.as_ptr().cast::<u8>();
+    let value = mem.wrapping_add(value_offset);
+    unsafe {
+        mem.add(alloc_offset).cast::<A>().write(alloc);
+        value.cast::<T>().write(v);
+        mem.cast::<Inline<C>>().write(Inline {
+            strong: C::new(1),
+            weak: C::new(1),
+            init: core::sync::atomic::AtomicBool::new(true),
+            value,
+            len: 1,
+            value_size: core::mem::size_of::<T>(),
+            layout,
+            type_id: core::any::TypeId::of::<T>(),
+            type_name: core::any::type_name::<T>(),
+            drop_value: drop_header_slice::<(), T>,
+            dealloc: dealloc_inline_in::<C, A, T>,
+        });
+        Ok((core::ptr::NonNull::new_unchecked(mem.cast()), value.cast()))
+    }
+}
+
+/// Frees an [`Inline`] allocation made by [`new_inline_in`], and then drops
+/// the allocator that was stored in it.
+#[cfg(feature = "experimental_allocator_api")]
+unsafe fn dealloc_inline_in<C, A: alloc::alloc::Allocator, T>(
+    mem: *mut u8,
+    layout: core::alloc::Layout,
+) {
+    let (_, alloc_offset, _) = inline_in_layout::<C, A, T>();
+    unsafe {
+        let alloc = mem.add(alloc_offset).cast::<A>().read();
+        alloc.deallocate(core::ptr::NonNull::new_unchecked(mem), layout);
+    }
+}
+
+/// Drops a `HeaderSlice<H, [T]>` with `len` elements in place, for use as the
+/// `drop_value` of an [`Inline`] allocation.
+#[allow(unused)]
+unsafe fn drop_header_slice<H, T>(value: *mut u8, len: usize) {
+    let ptr =
+        core::ptr::slice_from_raw_parts_mut(value.cast::<T>(), len) as *mut HeaderSlice<H, [T]>;
+    unsafe { core::ptr::drop_in_place(ptr) };
+}
+
+/// Returns a pointer with the address and provenance of `addr` but with the
+/// metadata of `meta`, if any.
+#[allow(unused)]
+fn with_metadata_of<T: ?Sized>(addr: *mut u8, meta: *const T) -> *mut T {
+    let mut ret = meta as *mut T;
+    // SAFETY: For a thin pointer this is the same as a simple assignment, and
+    // for a fat pointer the data pointer is always the first field.
+    unsafe { *(&mut ret as *mut *mut T as *mut *mut u8) = addr };
+    ret
+}
+
+/// Wrapper for the value in a reference-counted allocation that has no
+/// additional capabilities.
+#[allow(unused)]
+#[repr(transparent)]
+struct Plain<T>(T);
+
+/// Wrapper for the value in a reference-counted allocation whose type is
+/// known to implement [`Clone`].
+#[allow(unused)]
+#[repr(transparent)]
+struct Cloneable<T>(T);
+
+/// Wrapper for a pointer to a separate allocation, used as the value of a
+/// reference-counted allocation when the separate allocation cannot be
+/// type-erased directly, such as when its value is dynamically-sized.
+#[allow(unused)]
+struct Indirect<P>(P);
+
+/// Implemented by pointer types that can be wrapped in [`Indirect`].
+#[allow(unused)]
+trait IndirectOwner: core::ops::Deref {
+    /// Returns a mutable pointer to the target, or `None` if it is shared
+    /// with other pointers.
+    fn get_mut(&mut self) -> Option<*mut Self::Target>;
+}
+
+#[cfg(not(feature = "experimental_allocator_api"))]
+impl<T: ?Sized> IndirectOwner for alloc::boxed::Box<T> {
+    #[inline(always)]
+    fn get_mut(&mut self) -> Option<*mut T> {
+        Some(&mut **self)
+    }
+}
+
+#[cfg(feature = "experimental_allocator_api")]
+impl<T: ?Sized, A: alloc::alloc::Allocator> IndirectOwner for alloc::boxed::Box<T, A> {
+    #[inline(always)]
+    fn get_mut(&mut self) -> Option<*mut T> {
+        Some(&mut **self)
+    }
+}
+
+impl<T> IndirectOwner for alloc::vec::Vec<T> {
+    #[inline(always)]
+    fn get_mut(&mut self) -> Option<*mut [T]> {
+        Some(self.as_mut_slice())
+    }
+}
+
+impl IndirectOwner for alloc::string::String {
+    #[inline(always)]
+    fn get_mut(&mut self) -> Option<*mut str> {
+        Some(self.as_mut_str())
+    }
+}
+
+impl<T: ?Sized> IndirectOwner for alloc::rc::Rc<T> {
+    #[inline(always)]
+    fn get_mut(&mut self) -> Option<*mut T> {
+        alloc::rc::Rc::get_mut(self).map(|v| v as *mut T)
+    }
+}
+
+impl<T: ?Sized> IndirectOwner for alloc::sync::Arc<T> {
+    #[inline(always)]
+    fn get_mut(&mut self) -> Option<*mut T> {
+        alloc::sync::Arc::get_mut(self).map(|v| v as *mut T)
+    }
+}
+
+/// Wrapper for the value in a reference-counted allocation that is owned
+/// by a unique pointer until it is frozen, after which it can be shared.
+#[allow(unused)]
+struct Unique<T> {
+    frozen: core::sync::atomic::AtomicBool,
+    value: core::cell::UnsafeCell<T>,
+}
+
+// SAFETY: The value is only accessed mutably through the unique pointer
+// before the allocation is frozen, and only shared afterwards.
+unsafe impl<T: Send + Sync> Sync for Unique<T> {}
+
+#[allow(unused)]
+impl<T> Unique<T> {
+    fn new(v: T) -> Self {
+        Self {
+            frozen: core::sync::atomic::AtomicBool::new(false),
+            value: core::cell::UnsafeCell::new(v),
+        }
+    }
+}
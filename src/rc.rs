@@ -1,11 +1,31 @@
 //! Single-threaded reference-counting pointers. ‘Rc’ stands for ‘Reference Counted’.
 //!
 //! This module is only included when the "rc" feature is enabled, but that feature is enabled by default.
+//!
+//! Functions that derive new pointers from references to the target only accept references that
+//! are derived from the target, so a pointer cannot refer to anything outside of its allocation:
+//!
+//! ```compile_fail
+//! let local = vec![String::from("not in the allocation")];
+//! let p = maprc::rc::Rc::new(1_u64);
+//! let iter = maprc::rc::Rc::iter_map(&p, |_: &u64| &local);
+//! ```
 
 pub use crate::macroed::rc::*;
 
 impl<T> core::panic::UnwindSafe for Rc<T> {}
 impl<T> core::panic::UnwindSafe for Weak<T> {}
+impl<T> core::panic::UnwindSafe for ScopedRc<'_, T> {}
+impl<T> core::panic::UnwindSafe for ScopedWeak<'_, T> {}
+
+impl Rc<dyn core::any::Any> {
+    /// Attempts to downcast the target to a concrete type, within the same allocation.
+    ///
+    /// Returns the original pointer if the target is not of type `T`.
+    pub fn downcast<T: core::any::Any>(this: Self) -> Result<Rc<T>, Self> {
+        Self::filter_map(this, |v| v.downcast_ref::<T>())
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -82,4 +102,819 @@ mod tests {
         assert_eq!(foo_a.map(|r| *r), Some(3));
         assert_eq!(foo_b.map(|r| *r), None);
     }
+
+    #[test]
+    pub fn map() {
+        struct Foo {
+            a: u64,
+            b: u64,
+        }
+        let foo = Rc::new(Foo { a: 3, b: 4 });
+        let foo_b: Rc<u64> = Rc::clone_map(&foo, |foo| &foo.b);
+        let foo_a: Rc<u64> = Rc::map(foo, |foo| &foo.a);
+        assert_eq!(
+            Rc::strong_count(&foo_a),
+            2,
+            "pointer to a has wrong strong_count",
+        );
+        assert_eq!(*foo_a, 3);
+        assert_eq!(*foo_b, 4);
+    }
+
+    #[test]
+    pub fn filter_map() {
+        let p = Rc::new(Some(3_u64));
+        let p: Rc<u64> = Rc::filter_map(p, |v| v.as_ref()).ok().unwrap();
+        assert_eq!(*p, 3);
+
+        let p = Rc::new(None::<u64>);
+        let p: Rc<Option<u64>> = Rc::filter_map(p, |v| v.as_ref()).unwrap_err();
+        assert_eq!(*p, None);
+    }
+
+    #[test]
+    pub fn try_map() {
+        let p = Rc::new([1_u64, 2, 3]);
+        let elem: Rc<u64> = Rc::try_map(p, |v| v.get(1).ok_or("out of range"))
+            .ok()
+            .unwrap();
+        assert_eq!(*elem, 2);
+        assert_eq!(Rc::strong_count(&elem), 1);
+
+        let p = Rc::new([1_u64, 2, 3]);
+        let (p, err) = Rc::try_map(p, |v| v.get(5).ok_or("out of range"))
+            .err()
+            .unwrap();
+        assert_eq!(*p, [1, 2, 3]);
+        assert_eq!(err, "out of range");
+    }
+
+    #[test]
+    pub fn map_split() {
+        struct Foo {
+            a: u64,
+            b: u64,
+            c: [u8; 2],
+        }
+        let foo = Rc::new(Foo {
+            a: 3,
+            b: 4,
+            c: *b"hi",
+        });
+        let (foo_a, foo_b) = Rc::map_split(Rc::clone(&foo), |foo| (&foo.a, &foo.b));
+        assert_eq!(*foo_a, 3);
+        assert_eq!(*foo_b, 4);
+        assert_eq!(
+            Rc::strong_count(&foo),
+            3,
+            "pointer to foo has wrong strong_count",
+        );
+        drop((foo_a, foo_b));
+
+        let (foo_a, foo_b, foo_c) = Rc::map_split3(foo, |foo| (&foo.a, &foo.b, &foo.c[..]));
+        assert_eq!(*foo_a, 3);
+        assert_eq!(*foo_b, 4);
+        assert_eq!(&*foo_c, b"hi");
+        assert_eq!(
+            Rc::strong_count(&foo_c),
+            3,
+            "pointer to c has wrong strong_count",
+        );
+    }
+
+    #[test]
+    pub fn iter_map() {
+        let v = Rc::new(alloc::vec![1_u64, 2, 3]);
+        let iter = Rc::iter_map(&v, |v| v);
+        assert_eq!(iter.len(), 3);
+        let elems: alloc::vec::Vec<Rc<u64>> = iter.rev().collect();
+        drop(v); // The elements can safely outlive the original
+        assert_eq!(
+            Rc::strong_count(&elems[0]),
+            3,
+            "pointer to element has wrong strong_count",
+        );
+        assert_eq!(*elems[0], 3);
+        assert_eq!(*elems[1], 2);
+        assert_eq!(*elems[2], 1);
+    }
+
+    #[test]
+    pub fn slice() {
+        let buf = Rc::new([0_u8, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let all: Rc<[u8]> = Rc::clone_map(&buf, |b| &b[..]);
+        let part = Rc::slice(&all, 3..7);
+        assert_eq!(&*part, &[3, 4, 5, 6]);
+
+        let (l, r) = Rc::split_at(&part, 1);
+        assert_eq!(&*l, &[3]);
+        assert_eq!(&*r, &[4, 5, 6]);
+
+        let (first, rest) = Rc::split_first(&r).unwrap();
+        assert_eq!(*first, 4);
+        assert_eq!(&*rest, &[5, 6]);
+        let (last, rest) = Rc::split_last(&r).unwrap();
+        assert_eq!(*last, 6);
+        assert_eq!(&*rest, &[4, 5]);
+        assert!(Rc::split_first(&Rc::slice(&all, 0..0)).is_none());
+
+        let chunks: alloc::vec::Vec<Rc<[u8]>> = Rc::chunks(&all, 4).collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(&*chunks[2], &[8, 9]);
+
+        let arr: Rc<[u8; 4]> = Rc::slice(&all, 4..8).try_into().unwrap();
+        assert_eq!(*arr, [4, 5, 6, 7]);
+        let not_arr: Result<Rc<[u8; 4]>, _> = Rc::slice(&all, 4..9).try_into();
+        assert_eq!(&*not_arr.unwrap_err(), &[4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    pub fn slice_str() {
+        let buf = Rc::new(*b"hello world\nsecond line");
+        let bytes: Rc<[u8]> = Rc::clone_map(&buf, |b| &b[..]);
+        let s = Rc::<str>::from_utf8(bytes).unwrap();
+        assert_eq!(&*Rc::slice(&s, 6..11), "world");
+
+        let (l, r) = Rc::split_at(&s, 5);
+        assert_eq!(&*l, "hello");
+        assert_eq!(&*r, " world\nsecond line");
+
+        let lines: alloc::vec::Vec<Rc<str>> = Rc::lines(&s).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(&*lines[1], "second line");
+
+        let words: alloc::vec::Vec<Rc<str>> = Rc::split(&lines[1], " ").collect();
+        assert_eq!(words.len(), 2);
+        assert_eq!(&*words[0], "second");
+
+        let bad: Rc<[u8]> = Rc::map(Rc::new([0xff_u8, 0xfe]), |b| &b[..]);
+        let (bad, _) = Rc::<str>::from_utf8(bad).unwrap_err();
+        assert_eq!(&*bad, &[0xff, 0xfe]);
+    }
+
+    #[test]
+    pub fn identity() {
+        struct Foo {
+            a: u64,
+            b: u64,
+        }
+        let foo = Rc::new(Foo { a: 3, b: 4 });
+        let foo_a: Rc<u64> = Rc::clone_map(&foo, |foo| &foo.a);
+        let foo_b: Rc<u64> = Rc::clone_map(&foo, |foo| &foo.b);
+        let other = Rc::new(3_u64);
+        assert!(Rc::ptr_eq(&foo_a, &Rc::clone(&foo_a)));
+        assert!(!Rc::ptr_eq(&foo_a, &foo_b));
+        assert!(!Rc::ptr_eq(&foo_a, &other));
+        assert!(Rc::same_allocation(&foo_a, &foo_b));
+        assert!(Rc::same_allocation(&foo_a, &foo));
+        assert!(!Rc::same_allocation(&foo_a, &other));
+
+        let offset_b = core::mem::offset_of!(Foo, b);
+        assert_eq!(Rc::offset_in_allocation(&foo_b), Some(offset_b));
+        assert_eq!(
+            Rc::byte_range_within(&foo_b, &foo),
+            Some(offset_b..offset_b + 8)
+        );
+        assert_eq!(Rc::byte_range_within(&foo_a, &foo_b), None);
+
+        static S: &str = "hello world";
+        let s = Rc::from_static_ref(S);
+        let world = Rc::slice(&s, 6..);
+        assert_eq!(Rc::byte_range_within(&world, &s), Some(6..11));
+        assert_eq!(Rc::offset_in_allocation(&world), None);
+        assert!(!Rc::same_allocation(&world, &s));
+        assert!(Rc::ptr_eq(&world, &Rc::from_static_ref(&S[6..])));
+    }
+
+    #[test]
+    pub fn by_address() {
+        use alloc::collections::BTreeSet;
+
+        let v = Rc::new([1_u64, 1]);
+        let a: Rc<u64> = Rc::clone_map(&v, |v| &v[0]);
+        let b: Rc<u64> = Rc::clone_map(&v, |v| &v[1]);
+        assert_eq!(a, b, "values should be equal");
+
+        let mut set = BTreeSet::new();
+        assert!(set.insert(ByAddress(a.clone())));
+        assert!(set.insert(ByAddress(b)));
+        assert!(!set.insert(ByAddress(a)));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    pub fn get_mut() {
+        struct Foo {
+            a: u64,
+            b: [u8; 4],
+        }
+        let foo = Rc::new(Foo { a: 3, b: *b"abcd" });
+        assert_eq!(foo.a, 3);
+        let mut foo_b: Rc<[u8]> = Rc::map(foo, |foo| &foo.b[..]);
+        Rc::get_mut(&mut foo_b).unwrap()[1] = b'x';
+        assert_eq!(&*foo_b, b"axcd");
+
+        let other = Rc::clone(&foo_b);
+        assert!(
+            Rc::get_mut(&mut foo_b).is_none(),
+            "shared target is mutable"
+        );
+        drop(other);
+        let weak = Rc::downgrade(&foo_b);
+        assert!(
+            Rc::get_mut(&mut foo_b).is_none(),
+            "shared target is mutable"
+        );
+        drop(weak);
+        assert!(Rc::get_mut(&mut foo_b).is_some());
+
+        static V: u64 = 3;
+        let mut s = Rc::from_static_ref(&V);
+        assert!(Rc::get_mut(&mut s).is_none(), "static target is mutable");
+
+        let mut v = Rc::new(alloc::vec![1_u64, 2, 3]);
+        Rc::get_mut(&mut v).unwrap().push(4);
+        let mut elem: Rc<u64> = Rc::map(v, |v| &v[3]);
+        assert_eq!(*elem, 4);
+        assert!(
+            Rc::get_mut(&mut elem).is_none(),
+            "target outside of allocation is mutable"
+        );
+    }
+
+    #[test]
+    pub fn make_mut() {
+        #[derive(Clone)]
+        struct Foo {
+            a: u64,
+            b: u64,
+        }
+        // SAFETY: The derived Clone implementation preserves the layout.
+        let foo = unsafe { Rc::new_cloneable(Foo { a: 3, b: 4 }) };
+        let mut foo_b: Rc<u64> = Rc::clone_map(&foo, |foo| &foo.b);
+        *Rc::make_mut(&mut foo_b).unwrap() = 5;
+        assert_eq!(*foo_b, 5);
+        assert_eq!(foo.b, 4, "original allocation was modified");
+        assert!(!Rc::same_allocation(&foo, &foo_b));
+        assert_eq!(Rc::strong_count(&foo), 1);
+        assert_eq!(Rc::strong_count(&foo_b), 1);
+
+        // Now that foo_b is unique, it should be modified in place.
+        let before = Rc::as_ptr(&foo_b);
+        *Rc::make_mut(&mut foo_b).unwrap() = 6;
+        assert_eq!(*foo_b, 6);
+        assert_eq!(Rc::as_ptr(&foo_b), before);
+        assert_eq!(foo.a, 3);
+
+        let mut not_cloneable = Rc::new(1_u64);
+        let _other = Rc::clone(&not_cloneable);
+        assert!(Rc::make_mut(&mut not_cloneable).is_none());
+    }
+
+    #[test]
+    pub fn try_unwrap() {
+        let p = Rc::new(alloc::string::String::from("hello"));
+        let other = Rc::clone(&p);
+        let p = Rc::try_unwrap(p).unwrap_err();
+        drop(other);
+        assert_eq!(Rc::try_unwrap(p).unwrap(), "hello");
+
+        let p = Rc::new((1_u64, 2_u64));
+        let first: Rc<u64> = Rc::map(p, |p| &p.0);
+        let first = Rc::try_unwrap(first).unwrap_err();
+        assert_eq!(Rc::unwrap_or_clone(first), 1);
+
+        let p = unsafe { Rc::new_cloneable(3_u64) };
+        let other = Rc::clone(&p);
+        let mut p = Rc::try_unwrap(p).unwrap_err();
+        *Rc::make_mut(&mut p).unwrap() = 4;
+        assert_eq!(Rc::into_inner(other), Some(3));
+        assert_eq!(Rc::into_inner(p), Some(4));
+
+        static V: u64 = 5;
+        let s = Rc::from_static_ref(&V);
+        assert!(Rc::try_unwrap(s.clone()).is_err());
+        assert_eq!(Rc::unwrap_or_clone(s), 5);
+    }
+
+    #[test]
+    pub fn try_into_alloc() {
+        let normal = alloc::rc::Rc::new(24_u64);
+        let p = Rc::from_alloc(alloc::rc::Rc::clone(&normal));
+        let back = Rc::try_into_alloc(p).unwrap();
+        assert!(alloc::rc::Rc::ptr_eq(&normal, &back));
+
+        let p = Rc::new((1_u64, 2_u64));
+        let second: Rc<u64> = Rc::map(p, |p| &p.1);
+        assert!(Rc::try_into_alloc(second).is_err());
+    }
+
+    #[test]
+    pub fn leak() {
+        let p = Rc::new(24_u64);
+        let weak = Rc::downgrade(&p);
+        let r: &'static u64 = Rc::leak(p);
+        assert_eq!(*r, 24);
+        assert!(weak.upgrade().is_some(), "leaked allocation was freed");
+
+        static V: u64 = 5;
+        assert!(core::ptr::eq(Rc::leak(Rc::from_static_ref(&V)), &V));
+    }
+
+    #[test]
+    pub fn unique() {
+        struct Node {
+            name: alloc::string::String,
+            parent: Weak<Node>,
+            children: alloc::vec::Vec<Rc<Node>>,
+        }
+        let mut root = UniqueRc::new(Node {
+            name: "root".into(),
+            parent: Weak::new(),
+            children: alloc::vec::Vec::new(),
+        });
+        let weak_root = UniqueRc::downgrade(&root);
+        assert!(
+            weak_root.upgrade().is_none(),
+            "weak upgraded before freezing"
+        );
+        for i in 0..2 {
+            let child = Rc::new(Node {
+                name: alloc::format!("child{i}"),
+                parent: UniqueRc::downgrade(&root),
+                children: alloc::vec::Vec::new(),
+            });
+            root.children.push(child);
+        }
+        let mut name = UniqueRc::map_mut(root, |n| &mut n.name);
+        name.push('!');
+        let name: Rc<alloc::string::String> = UniqueRc::into_rc(name);
+        assert_eq!(*name, "root!");
+
+        let root = weak_root
+            .upgrade()
+            .expect("weak not upgradeable after freezing");
+        assert_eq!(root.children.len(), 2);
+        let child_parent = root.children[1].parent.upgrade().unwrap();
+        assert_eq!(child_parent.name, "root!");
+        assert_eq!(Rc::strong_count(&root), 3);
+    }
+
+    #[test]
+    pub fn unique_dropped() {
+        let p = UniqueRc::new(5_u64);
+        let weak = UniqueRc::downgrade(&p);
+        drop(p);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(), 0);
+    }
+
+    #[test]
+    pub fn weak() {
+        let empty: Weak<str> = Weak::new();
+        assert!(empty.upgrade().is_none());
+        assert_eq!(empty.strong_count(), 0);
+        assert!(!empty.target_is_static());
+        assert!(empty.ptr_eq(&Weak::default()));
+
+        let p = Rc::new((1_u64, 2_u64));
+        let first = Rc::downgrade(&Rc::clone_map(&p, |p| &p.0));
+        let second = Rc::downgrade(&Rc::clone_map(&p, |p| &p.1));
+        let first_again = first.clone();
+        assert_eq!(first.weak_count(), 3);
+        assert!(first.ptr_eq(&first_again));
+        assert!(!first.ptr_eq(&second));
+        assert!(!first.ptr_eq(&Weak::new()));
+        assert!(first.same_allocation(&second));
+        assert!(!first.same_allocation(&Rc::downgrade(&Rc::new(1_u64))));
+        assert_eq!(alloc::format!("{:?}", first), "(Weak)");
+        assert_eq!(first_again.upgrade().map(|p| *p), Some(1));
+    }
+
+    #[test]
+    pub fn try_new_cyclic() {
+        struct Node {
+            me: Weak<Node>,
+            name: alloc::string::String,
+        }
+        let node = Rc::try_new_cyclic(|me| {
+            Ok::<_, ()>(Node {
+                me: me.clone(),
+                name: "a".into(),
+            })
+        })
+        .unwrap();
+        assert_eq!(node.me.upgrade().unwrap().name, "a");
+
+        let mut kept = None;
+        let err = Rc::try_new_cyclic(|me: &Weak<Node>| {
+            kept = Some(me.clone());
+            Err("invalid")
+        });
+        assert_eq!(err.err(), Some("invalid"));
+        let kept = kept.unwrap();
+        assert!(kept.upgrade().is_none());
+        assert_eq!(kept.strong_count(), 0);
+    }
+
+    #[test]
+    pub fn weak_clone_map() {
+        let p = Rc::new((1_u64, Some(2_u64)));
+        let weak = Rc::downgrade(&p);
+        let first: Weak<u64> = weak.clone_map(|p| &p.0).unwrap();
+        let second: Weak<u64> = weak.clone_filter_map(|p| p.1.as_ref()).unwrap();
+        assert!(weak.clone_filter_map(|_| None::<&u64>).is_none());
+        assert_eq!(Rc::strong_count(&p), 1);
+        assert_eq!(Rc::weak_count(&p), 3);
+        assert!(first.same_allocation(&weak));
+        assert_eq!(first.upgrade().map(|p| *p), Some(1));
+        assert_eq!(second.upgrade().map(|p| *p), Some(2));
+
+        drop(p);
+        assert!(weak.clone_map(|p| &p.0).is_none());
+        assert!(first.upgrade().is_none());
+
+        static V: (u64, u64) = (3, 4);
+        let weak = Weak::from_static_ref(&V);
+        let second = weak.clone_map(|v| &v.1).unwrap();
+        assert!(second.target_is_static());
+        assert_eq!(second.upgrade().map(|p| *p), Some(4));
+    }
+
+    #[test]
+    pub fn downcast() {
+        let p = Rc::new((1_u64, alloc::string::String::from("hi")));
+        let second: Rc<dyn core::any::Any> = Rc::clone_map(&p, |p| &p.1 as &dyn core::any::Any);
+        let second = Rc::downcast::<u64>(second).unwrap_err();
+        let second = Rc::downcast::<alloc::string::String>(second).unwrap();
+        assert_eq!(*second, "hi");
+        assert!(Rc::same_allocation(&second, &p));
+    }
+
+    #[test]
+    pub fn owner_downcast() {
+        struct Document {
+            title: alloc::string::String,
+            body: alloc::string::String,
+        }
+        let doc = Rc::new(Document {
+            title: "Title".into(),
+            body: "Body".into(),
+        });
+        let title: Rc<str> = Rc::map(doc, |d| d.title.as_str());
+        assert!(Rc::owner_downcast::<alloc::string::String>(&title).is_none());
+        let doc = Rc::owner_downcast::<Document>(&title).unwrap();
+        assert_eq!(doc.body, "Body");
+        assert_eq!(Rc::strong_count(&doc), 2);
+
+        let mut unique = UniqueRc::new((1_u64, 2_u64));
+        unique.1 = 3;
+        let second = Rc::map(UniqueRc::into_rc(unique), |p| &p.1);
+        let whole = Rc::owner_downcast::<(u64, u64)>(&second).unwrap();
+        assert_eq!(*whole, (1, 3));
+
+        static V: u64 = 3;
+        assert!(Rc::owner_downcast::<u64>(&Rc::from_static_ref(&V)).is_none());
+    }
+
+    #[test]
+    pub fn from_unsized_alloc() {
+        let std_str: alloc::rc::Rc<str> = alloc::rc::Rc::from("hello world");
+        let addr = std_str.as_ptr();
+        let p: Rc<str> = std_str.into();
+        assert_eq!(&*p, "hello world");
+        assert_eq!(p.as_ptr(), addr, "value was copied");
+        assert_eq!(Rc::allocation_size(&p), 11);
+        let second = Rc::slice(&p, 6..);
+        assert_eq!(Rc::offset_in_allocation(&second), Some(6));
+
+        let mut p: Rc<[u8]> = alloc::rc::Rc::<[u8]>::from([1_u8, 2, 3]).into();
+        Rc::get_mut(&mut p).unwrap()[0] = 4;
+        assert_eq!(*p, [4, 2, 3]);
+
+        let b: alloc::boxed::Box<dyn core::fmt::Debug> = alloc::boxed::Box::new(24_u64);
+        let p: Rc<dyn core::fmt::Debug> = b.into();
+        assert_eq!(alloc::format!("{p:?}"), "24");
+    }
+
+    #[test]
+    pub fn from_vec() {
+        let v = alloc::vec![1_u8, 2, 3, 4];
+        let addr = v.as_ptr();
+        let mut p: Rc<[u8]> = v.into();
+        assert_eq!(p.as_ptr(), addr, "elements were copied");
+        assert_eq!(Rc::allocation_size(&p), 4);
+        Rc::get_mut(&mut p).unwrap()[0] = 5;
+        assert_eq!(*p, [5, 2, 3, 4]);
+        let second = Rc::slice(&p, 2..);
+        assert_eq!(Rc::offset_in_allocation(&second), Some(2));
+
+        let s = alloc::string::String::from("hello");
+        let addr = s.as_ptr();
+        let p = Rc::from_string(s);
+        assert_eq!(p.as_ptr(), addr, "contents were copied");
+        assert_eq!(&*p, "hello");
+
+        let b: alloc::boxed::Box<str> = "boxed".into();
+        let addr = b.as_ptr();
+        let p: Rc<str> = b.into();
+        assert_eq!(p.as_ptr(), addr, "contents were copied");
+    }
+
+    #[test]
+    pub fn from_header_and_iter() {
+        let p = Rc::from_header_and_iter(
+            alloc::string::String::from("head"),
+            (1..4_u16).map(|v| v * 2),
+        );
+        let header = Rc::clone_map(&p, |v| v.header.as_str());
+        let slice = Rc::clone_map(&p, |v| &v.slice);
+        drop(p);
+        assert_eq!(&*header, "head");
+        assert_eq!(*slice, [2, 4, 6]);
+        assert!(Rc::same_allocation(&header, &slice));
+        assert!(Rc::offset_in_allocation(&slice).is_some());
+
+        let empty = Rc::from_header_and_iter((), core::iter::empty::<()>());
+        assert_eq!(empty.slice.len(), 0);
+
+        let collected: Rc<[u32]> = (1..=3).collect();
+        assert_eq!(*collected, [1, 2, 3]);
+
+        // The header, the elements and the counts share one allocation.
+        let tracker = alloc::rc::Rc::new(());
+        let p = Rc::from_header_and_iter(tracker.clone(), core::iter::repeat_n(tracker.clone(), 3));
+        assert_eq!(alloc::rc::Rc::strong_count(&tracker), 5);
+        let mut slice = Rc::clone_map(&p, |v| &v.slice);
+        let weak = Rc::downgrade(&slice);
+        assert_eq!(Rc::strong_count(&p), 2);
+        assert_eq!(Rc::weak_count(&p), 1);
+        assert!(Rc::get_mut(&mut slice).is_none());
+        drop(p);
+        assert!(weak.upgrade().is_some());
+        drop(slice);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(), 0);
+        assert_eq!(alloc::rc::Rc::strong_count(&tracker), 1);
+        drop(weak);
+
+        // An iterator whose length isn't known up front is collected first.
+        let mut collected: Rc<[alloc::rc::Rc<()>]> = core::iter::repeat_n(tracker.clone(), 4)
+            .filter(|_| true)
+            .collect();
+        assert_eq!(collected.len(), 4);
+        assert!(Rc::get_mut(&mut collected).is_some());
+        assert_eq!(alloc::rc::Rc::strong_count(&tracker), 5);
+        drop(collected);
+        assert_eq!(alloc::rc::Rc::strong_count(&tracker), 1);
+    }
+
+    /// An iterator that reports a fixed length regardless of how many items
+    /// it actually produces.
+    struct WrongLen(core::ops::Range<u32>, usize);
+
+    impl Iterator for WrongLen {
+        type Item = u32;
+
+        fn next(&mut self) -> Option<u32> {
+            self.0.next()
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (self.1, Some(self.1))
+        }
+    }
+
+    impl ExactSizeIterator for WrongLen {}
+
+    #[test]
+    pub fn from_iter_wrong_len() {
+        let collected: Rc<[u32]> = WrongLen(1..6, 3).collect();
+        assert_eq!(*collected, [1, 2, 3, 4, 5]);
+        let collected: Rc<[u32]> = WrongLen(1..3, 4).collect();
+        assert_eq!(*collected, [1, 2]);
+        let collected: Rc<[u32]> = WrongLen(1..1, 0).collect();
+        assert_eq!(collected.len(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "different number of items")]
+    pub fn from_header_and_iter_too_many() {
+        Rc::from_header_and_iter((), WrongLen(1..6, 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "different number of items")]
+    pub fn from_header_and_iter_too_few() {
+        Rc::from_header_and_iter((), WrongLen(1..3, 4));
+    }
+
+    #[test]
+    pub fn new_uninit() {
+        let mut p = Rc::<alloc::string::String>::new_uninit();
+        Rc::get_mut(&mut p).unwrap().write("hello".into());
+        let p = unsafe { Rc::assume_init(p) };
+        assert_eq!(*p, "hello");
+        assert!(Rc::owner_downcast::<alloc::string::String>(&p).is_some());
+
+        let p = unsafe { Rc::assume_init(Rc::<u64>::new_zeroed()) };
+        assert_eq!(*p, 0);
+
+        let mut p = Rc::<alloc::string::String>::new_uninit_slice(2);
+        for (i, elem) in Rc::get_mut(&mut p).unwrap().iter_mut().enumerate() {
+            elem.write(alloc::format!("{i}"));
+        }
+        let p = unsafe { Rc::assume_init_slice(p) };
+        assert_eq!(*p, ["0", "1"]);
+        assert!(Rc::offset_in_allocation(&p).is_some());
+
+        // The elements are dropped only once they have been marked as initialized.
+        let tracker = alloc::rc::Rc::new(());
+        let mut p = Rc::<alloc::rc::Rc<()>>::new_uninit_slice(2);
+        for elem in Rc::get_mut(&mut p).unwrap() {
+            elem.write(tracker.clone());
+        }
+        let p = unsafe { Rc::assume_init_slice(p) };
+        assert_eq!(alloc::rc::Rc::strong_count(&tracker), 3);
+        drop(p);
+        assert_eq!(alloc::rc::Rc::strong_count(&tracker), 1);
+        drop(Rc::<alloc::rc::Rc<()>>::new_uninit_slice(2));
+
+        // Another pointer to the allocation keeps the elements uninitialized.
+        let mut p = Rc::<alloc::rc::Rc<()>>::new_uninit_slice(1);
+        Rc::get_mut(&mut p).unwrap()[0].write(tracker.clone());
+        let mut q = p.clone();
+        drop(unsafe { Rc::assume_init_slice(p) });
+        assert_eq!(alloc::rc::Rc::strong_count(&tracker), 2);
+        unsafe { Rc::get_mut(&mut q).unwrap()[0].assume_init_drop() };
+        drop(q);
+        assert_eq!(
+            alloc::rc::Rc::strong_count(&tracker),
+            1,
+            "elements were dropped twice"
+        );
+    }
+
+    #[cfg(feature = "experimental_allocator_api")]
+    #[test]
+    pub fn new_in() {
+        use alloc::alloc::{AllocError, Allocator, Global, Layout};
+        use core::ptr::NonNull;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static LIVE: AtomicUsize = AtomicUsize::new(0);
+        static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+        struct Counting;
+        unsafe impl Allocator for Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                LIVE.fetch_add(1, Ordering::Relaxed);
+                LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+                Global.allocate(layout)
+            }
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                LIVE.fetch_sub(1, Ordering::Relaxed);
+                LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+                unsafe { Global.deallocate(ptr, layout) }
+            }
+        }
+
+        let p = Rc::new_in((1_u64, 2_u64), Counting);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 1);
+        assert!(
+            LIVE_BYTES.load(Ordering::Relaxed) > 16 + 2 * core::mem::size_of::<usize>(),
+            "reference counts are not in the allocator"
+        );
+        let second = Rc::map(p, |v| &v.1);
+        assert_eq!(*second, 2);
+        assert_eq!(Rc::offset_in_allocation(&second), Some(8));
+        let third = Rc::try_new_in(3_u64, Counting).unwrap();
+        assert_eq!(LIVE.load(Ordering::Relaxed), 2);
+        let weak = Rc::downgrade(&second);
+        drop((second, third));
+        assert_eq!(LIVE.load(Ordering::Relaxed), 1);
+        assert!(weak.upgrade().is_none());
+        drop(weak);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+        assert_eq!(LIVE_BYTES.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    pub fn unsized_traits() {
+        use alloc::borrow::Cow;
+        let p: Rc<str> = "hello".into();
+        let set: alloc::collections::BTreeSet<Rc<str>> =
+            [p.clone(), "world".into()].into_iter().collect();
+        assert!(set.contains("hello"));
+        assert!(p == "hello");
+        assert!(p == *"hello");
+        assert_eq!(AsRef::<str>::as_ref(&p), "hello");
+
+        let borrowed: Rc<str> = Cow::Borrowed("static").into();
+        assert!(Rc::target_is_static(&borrowed));
+        let owned: Rc<str> = Cow::<'static, str>::Owned("owned".into()).into();
+        assert_eq!(owned, "owned");
+        assert!(!Rc::target_is_static(&owned));
+
+        let s: Rc<[u8]> = [1_u8, 2, 3].as_slice().into();
+        assert!(s == *[1_u8, 2, 3].as_slice());
+        let mut sum = 0;
+        for v in &s {
+            sum += v;
+        }
+        assert_eq!(sum, 6);
+    }
+
+    #[test]
+    pub fn debug_info() {
+        let p = Rc::new((1_u32, 2_u32));
+        let second = Rc::clone_map(&p, |v| &v.1);
+        assert_eq!(alloc::format!("{second:p}"), alloc::format!("{:p}", &p.1));
+        let info = alloc::format!("{:?}", Rc::debug_info(&second));
+        let value = alloc::format!("allocation_value: {:p},", &*p);
+        assert!(info.contains(&value), "{info}");
+        assert!(info.contains("allocation_size: 8"), "{info}");
+        assert!(info.contains("allocation_type: (u32, u32)"), "{info}");
+        assert!(info.contains("offset: Some(4)"), "{info}");
+        assert!(info.contains("strong_count: 2"), "{info}");
+
+        static V: u32 = 1;
+        let info = alloc::format!("{:?}", Rc::debug_info(&Rc::from_static_ref(&V)));
+        assert!(info.contains("is_static: true"), "{info}");
+    }
+
+    #[test]
+    pub fn scoped() {
+        struct Parsed<'a> {
+            words: alloc::vec::Vec<&'a str>,
+        }
+        let buf = alloc::string::String::from("hello scoped world");
+        let parsed = ScopedRc::new(Parsed {
+            words: buf.split(' ').collect(),
+        });
+        let second: ScopedRc<str> = ScopedRc::clone_map(&parsed, |p| p.words[1]);
+        let weak = ScopedRc::downgrade(&second);
+        assert_eq!(&*second, "scoped");
+        assert!(ScopedRc::same_allocation(&second, &parsed));
+        assert_eq!(ScopedRc::strong_count(&parsed), 2);
+        drop(parsed);
+        assert_eq!(&*weak.upgrade().unwrap(), "scoped");
+        drop(second);
+        assert!(weak.upgrade().is_none());
+
+        let p = Rc::new((1_u64, 2_u64));
+        let s: ScopedRc<u64> = Rc::map(p, |v| &v.1).into();
+        assert_eq!(*s, 2);
+
+        let local = 5_u64;
+        let r = ScopedRc::from_ref(&local);
+        assert!(ScopedRc::target_is_borrowed(&r));
+        assert_eq!(*r, 5);
+    }
+
+    #[test]
+    pub fn typed() {
+        struct Pair {
+            a: u64,
+            b: alloc::string::String,
+        }
+        let p = TypedRc::new(Pair {
+            a: 1,
+            b: "two".into(),
+        });
+        assert_eq!(
+            core::mem::size_of::<TypedRc<Pair, u64>>(),
+            2 * core::mem::size_of::<usize>()
+        );
+        let b: TypedRc<Pair, str> = TypedRc::clone_map(&p, |p| p.b.as_str());
+        assert_eq!(&*b, "two");
+        assert_eq!(TypedRc::owner(&b).a, 1);
+        assert!(TypedRc::same_allocation(&b, &p));
+        assert_eq!(TypedRc::strong_count(&p), 2);
+
+        let erased: Rc<str> = b.into();
+        assert_eq!(&*erased, "two");
+        assert!(Rc::owner_downcast::<Pair>(&erased).is_some());
+        assert_eq!(TypedRc::strong_count(&p), 2);
+    }
+
+    #[test]
+    pub fn coerce() {
+        use core::fmt::Debug;
+        let p = Rc::new(24_u64);
+        let d: Rc<dyn Debug> = crate::coerce!(p);
+        assert_eq!(alloc::format!("{d:?}"), "24");
+
+        let a = Rc::new([1_u8, 2, 3]);
+        let s: Rc<[u8]> = crate::coerce!(a);
+        assert_eq!(s.len(), 3);
+    }
+
+    #[cfg(feature = "experimental_coerce_unsized")]
+    #[test]
+    pub fn coerce_unsized() {
+        use core::fmt::Debug;
+        let p = Rc::new(24_u64);
+        let d: Rc<dyn Debug> = p;
+        assert_eq!(alloc::format!("{d:?}"), "24");
+    }
 }